@@ -1,17 +1,27 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
-#[command(name = "gpth-rs-cli", version, about = "Google Photos Takeout Helper - process zip files without extraction")]
+#[command(
+    name = "gpth-rs-cli",
+    version,
+    about = "Google Photos Takeout Helper - process zip files without extraction",
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Google Takeout zip files
     #[arg(required = true)]
     zip_files: Vec<String>,
 
-    /// Output directory
-    #[arg(short, long)]
-    output: PathBuf,
+    /// Output directory (the archive file with --format zip or tar, s3://bucket/prefix with --format s3,
+    /// the collection URL with --format webdav)
+    #[arg(short, long, required = true)]
+    output: Option<PathBuf>,
 
     /// Organize into YYYY/MM subdirectories
     #[arg(long)]
@@ -44,20 +54,86 @@ struct Cli {
     /// Overwrite all files, ignore checkpoint and skip existence checks (fastest)
     #[arg(long)]
     force: bool,
+
+    /// On resume after changing options that decide output paths (--divide-to-dates, --no-guess,
+    /// --names, --case, --normalize), move files already written instead of starting fresh
+    #[arg(long)]
+    migrate: bool,
+
+    /// On resume, also check the content (CRC-32) of files written before the interruption, not just their size
+    #[arg(long)]
+    verify_resume: bool,
+
+    /// Content hash algorithm for duplicate detection
+    #[arg(long, value_enum, default_value_t = gpth_core::HashAlgorithm::Sha256)]
+    hash: gpth_core::HashAlgorithm,
+
+    /// fsync policy for written files: none, file (before rename) or full (file and directory)
+    #[arg(long, value_enum, default_value_t = gpth_core::writer::Durability::None)]
+    durability: gpth_core::writer::Durability,
+
+    /// Free space (MiB) to keep on the output drive; writing pauses below it until space is freed
+    #[arg(long, default_value_t = gpth_core::disk_space::DEFAULT_MIN_FREE_BYTES / (1024 * 1024))]
+    min_free_mb: u64,
+
+    /// Naming rules for output files and album folders: posix, windows (NTFS/SMB) or fat (FAT32/exFAT).
+    /// Defaults to the rules of this OS
+    #[arg(long, value_enum, default_value_t = gpth_core::sanitize::NameProfile::default())]
+    names: gpth_core::sanitize::NameProfile,
+
+    /// Whether names differing only in case collide: auto (detect), sensitive or insensitive
+    #[arg(long, value_enum, default_value_t = gpth_core::sanitize::CaseSensitivity::Auto)]
+    case: gpth_core::sanitize::CaseSensitivity,
+
+    /// Unicode normalization of output names: nfc (default), nfd or none
+    #[arg(long, value_enum, default_value_t = gpth_core::sanitize::NameNormalization::Nfc)]
+    normalize: gpth_core::sanitize::NameNormalization,
+
+    /// Files already in the output: skip, overwrite, rename (keep same-size files, default)
+    /// or compare (keep only files whose content hash matches)
+    #[arg(long, value_enum, default_value_t = gpth_core::writer::ConflictPolicy::Rename)]
+    on_conflict: gpth_core::writer::ConflictPolicy,
+
+    /// Output as a directory tree (dir), or stream everything into a single zip or tar file.
+    /// Archive output is not resumable. For --format webdav, the login is read from
+    /// WEBDAV_USER and WEBDAV_PASSWORD
+    #[arg(long, value_enum, default_value_t = gpth_core::sink::OutputFormat::Dir)]
+    format: gpth_core::sink::OutputFormat,
+
+    /// S3 endpoint URL, e.g. http://localhost:9000 for MinIO (default: $AWS_ENDPOINT_URL, then AWS).
+    /// Credentials are read from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY
+    #[cfg(feature = "s3")]
+    #[arg(long)]
+    s3_endpoint: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that a finished output matches its source zip files (exits non-zero on problems)
+    Verify {
+        /// Output directory of a previous run
+        output: PathBuf,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+
+    if let Some(Command::Verify { output }) = cli.command {
+        return verify(&output);
+    }
+
     let t_total = std::time::Instant::now();
+    let output = cli.output.expect("--output is required");
 
     // --force: delete checkpoint and skip all existence checks
     if cli.force {
-        let _ = gpth_core::Checkpoint::delete(&cli.output);
+        let _ = gpth_core::Checkpoint::delete(&output);
     }
 
     let options = gpth_core::ProcessOptions {
         zip_files: cli.zip_files,
-        output: cli.output.clone(),
+        output,
         divide_to_dates: cli.divide_to_dates,
         skip_extras: cli.skip_extras,
         no_guess: cli.no_guess,
@@ -66,6 +142,18 @@ fn main() -> anyhow::Result<()> {
         album_link: cli.album_link,
         album_json: cli.album_json,
         force: cli.force,
+        hash_algorithm: cli.hash,
+        durability: cli.durability,
+        min_free_bytes: cli.min_free_mb * 1024 * 1024,
+        name_profile: cli.names,
+        case_sensitivity: cli.case,
+        normalization: cli.normalize,
+        on_conflict: cli.on_conflict,
+        output_format: cli.format,
+        #[cfg(feature = "s3")]
+        s3_endpoint: cli.s3_endpoint,
+        #[cfg(not(feature = "s3"))]
+        s3_endpoint: None,
     };
 
     // Set up cancellation token and Ctrl+C handler
@@ -80,11 +168,11 @@ fn main() -> anyhow::Result<()> {
     // Auto-resume: always try to resume from checkpoint (unless --force)
     let control = gpth_core::ProcessControl::new()
         .with_resume(!cli.force)
+        .with_migrate(cli.migrate)
+        .with_verify_resume(cli.verify_resume)
         .with_cancel_token(cancel_token);
 
-    let result = gpth_core::process_with_control(&options, &control, &|stage, current, total, message| {
-        eprint!("\r[{}] {}/{} {}        ", stage, current + 1, total, message);
-    });
+    let result = gpth_core::process_with_control(&options, &control, &progress_printer());
 
     eprintln!(); // Clear the progress line
 
@@ -98,16 +186,167 @@ fn main() -> anyhow::Result<()> {
                 result.files_skipped,
                 t_total.elapsed().as_secs_f64()
             );
+            if result.files_moved > 0 {
+                eprintln!("{} file(s) moved to their new paths", result.files_moved);
+            }
+            if !result.drifted.is_empty() {
+                eprintln!("{} file(s) had changed since the interrupted run and were written again:", result.drifted.len());
+                for item in &result.drifted {
+                    eprintln!("  {}: {}", item.output_path.display(), item.reason);
+                }
+            }
+            if result.already_imported > 0 {
+                eprintln!(
+                    "{} new, {} already imported by an earlier run",
+                    result.files_written, result.already_imported
+                );
+            }
+            if !result.failed.is_empty() {
+                eprintln!("{} file(s) could not be written:", result.failed.len());
+                for item in &result.failed {
+                    eprintln!("  {} ({}): {}", item.zip_path, item.zip_file, item.cause);
+                }
+                eprintln!("Run again with the same options to retry only the failed files.");
+                std::process::exit(1);
+            }
             Ok(())
         }
-        Err(e) => {
-            if e.downcast_ref::<gpth_core::CancelledError>().is_some() {
-                eprintln!("Processing interrupted. Checkpoint saved.");
-                eprintln!("Run again to continue, or use --force to start fresh.");
-                std::process::exit(130); // Standard exit code for Ctrl+C
+        Err(gpth_core::Error::Cancelled) => {
+            eprintln!("Processing interrupted. Checkpoint saved.");
+            eprintln!("Run again to continue, or use --force to start fresh.");
+            std::process::exit(130); // Standard exit code for Ctrl+C
+        }
+        Err(e) => fail(&e),
+    }
+}
+
+/// Print the error with what to do about it, and exit non-zero.
+fn fail(e: &gpth_core::Error) -> ! {
+    eprintln!("Error: {:#}", e);
+    if let Some(hint) = e.hint() {
+        eprintln!("{}", hint);
+    }
+    std::process::exit(1)
+}
+
+/// Show progress on a single status line, adding bytes, throughput and ETA
+/// for stages that report them; finished stages and warnings get lines of their own.
+fn progress_printer() -> impl Fn(&gpth_core::ProgressEvent) + Send + Sync {
+    // Item count of the latest Progress event, repeated before its bytes
+    let items = std::sync::Mutex::new(String::new());
+    move |event| print_progress(event, &mut items.lock().unwrap())
+}
+
+fn print_progress(event: &gpth_core::ProgressEvent, items: &mut String) {
+    use gpth_core::disk_space::format_bytes;
+    use gpth_core::ProgressEvent;
+    match event {
+        ProgressEvent::Progress {
+            stage,
+            current,
+            total,
+            message,
+        } => {
+            *items = format!("[{}] {}/{} {}", stage, current, total, message);
+            eprint!("\r{}        ", items);
+        }
+        ProgressEvent::Bytes {
+            done,
+            total,
+            bytes_per_sec,
+            eta_secs,
+            ..
+        } => {
+            let eta = eta_secs.map_or_else(|| "--".to_string(), format_duration);
+            eprint!(
+                "\r{} ({} / {}, {}/s, ETA {})        ",
+                items,
+                format_bytes(*done),
+                format_bytes(*total),
+                format_bytes(*bytes_per_sec as u64),
+                eta
+            );
+        }
+        ProgressEvent::StageFinished {
+            stage,
+            elapsed_ms,
+            bytes,
+            bytes_per_sec,
+        } => {
+            let throughput = if *bytes > 0 {
+                format!(", {} at {}/s", format_bytes(*bytes), format_bytes(*bytes_per_sec as u64))
             } else {
-                Err(e)
+                String::new()
+            };
+            eprintln!("\r[{}] done in {:.1}s{}        ", stage, *elapsed_ms as f64 / 1000.0, throughput);
+        }
+        ProgressEvent::Paused { stage, message } => eprint!("\r[{}] {}        ", stage, message),
+        ProgressEvent::Warning { message } => eprintln!("\nWarning: {}", message),
+        ProgressEvent::Resumed {
+            written_files,
+            migrating,
+        } => {
+            eprintln!("Resuming from checkpoint: {} files already written", written_files);
+            if *migrating {
+                eprintln!("Output paths changed; written files will be moved to their new locations");
             }
         }
+        ProgressEvent::StageRestored { stage, media } => {
+            let done = match stage {
+                gpth_core::Stage::Write => "scanned, dated and deduplicated",
+                _ => "scanned and dated",
+            };
+            eprintln!("Resuming at {}: {} media already {}", stage, media, done);
+        }
+        _ => {}
+    }
+}
+
+/// `1h02m`, `3m05s` or `42s`.
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn verify(output: &std::path::Path) -> anyhow::Result<()> {
+    let printer = progress_printer();
+    let tp = gpth_core::ThrottledProgress::new(&printer);
+    let result = gpth_core::verify::verify_output(output, &tp).unwrap_or_else(|e| fail(&e));
+    eprintln!();
+
+    let sections = [
+        ("Missing", &result.missing),
+        ("Size mismatch", &result.truncated),
+        ("Hash mismatch", &result.mismatched),
+        ("Extra", &result.extra),
+    ];
+    for (label, paths) in sections {
+        for path in paths {
+            eprintln!("{}: {}", label, path.display());
+        }
+    }
+    for error in &result.errors {
+        eprintln!("Error: {}", error);
+    }
+    if result.incomplete_run {
+        eprintln!("Run did not complete; checked files written so far (size only).");
+    }
+
+    eprintln!(
+        "Checked {} files: {} missing, {} size mismatch, {} hash mismatch, {} extra, {} errors",
+        result.checked,
+        result.missing.len(),
+        result.truncated.len(),
+        result.mismatched.len(),
+        result.extra.len(),
+        result.errors.len()
+    );
+
+    if !result.is_ok() {
+        std::process::exit(1);
     }
+    Ok(())
 }
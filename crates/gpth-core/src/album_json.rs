@@ -3,12 +3,16 @@ use std::path::Path;
 
 use serde::Serialize;
 
+use crate::dedup::HashAlgorithm;
 use crate::media::Media;
+use crate::{Error, Result};
 
 #[derive(Serialize)]
 struct AlbumFile {
     filename: String,
     output_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -18,16 +22,31 @@ struct AlbumInfo {
 
 #[derive(Serialize)]
 struct AlbumsJson {
+    hash_algorithm: HashAlgorithm,
     albums: BTreeMap<String, AlbumInfo>,
 }
 
 /// Write albums.json mapping album names to their files and output paths.
+/// Files that were hashed during dedup carry their digest (see `hash_algorithm`).
 pub fn write_albums_json(
     media: &[Media],
     assignments: &[std::path::PathBuf],
     output_dir: &Path,
     album_json_path: &Path,
-) -> anyhow::Result<()> {
+    hash_algorithm: HashAlgorithm,
+) -> Result<()> {
+    let json = albums_json_bytes(media, assignments, output_dir, hash_algorithm)?;
+    std::fs::write(album_json_path, json).map_err(Error::io(album_json_path))?;
+    Ok(())
+}
+
+/// The albums.json document as bytes, for writing into an archive.
+pub fn albums_json_bytes(
+    media: &[Media],
+    assignments: &[std::path::PathBuf],
+    output_dir: &Path,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<u8>> {
     let mut albums: BTreeMap<String, Vec<AlbumFile>> = BTreeMap::new();
 
     for (m, dest) in media.iter().zip(assignments.iter()) {
@@ -40,19 +59,18 @@ pub fn write_albums_json(
             albums.entry(album_name.clone()).or_default().push(AlbumFile {
                 filename: m.filename.clone(),
                 output_path: relative,
+                hash: m.hash.clone(),
             });
         }
     }
 
     let json = AlbumsJson {
+        hash_algorithm,
         albums: albums
             .into_iter()
             .map(|(name, files)| (name, AlbumInfo { files }))
             .collect(),
     };
 
-    let file = std::fs::File::create(album_json_path)?;
-    serde_json::to_writer_pretty(file, &json)?;
-
-    Ok(())
+    Ok(serde_json::to_vec_pretty(&json).map_err(anyhow::Error::from)?)
 }
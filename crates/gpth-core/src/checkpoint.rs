@@ -1,23 +1,33 @@
 
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use zip::ZipArchive;
 
-use crate::ProcessOptions;
+use crate::dedup::HashAlgorithm;
+use crate::media::Media;
+use crate::writer::FailedItem;
+use crate::{Error, ProcessOptions, Result, ThrottledProgress};
 
 /// Current checkpoint file format version
-const CHECKPOINT_VERSION: u32 = 1;
+const CHECKPOINT_VERSION: u32 = 3;
 
 /// Default checkpoint filename
 pub const CHECKPOINT_FILENAME: &str = ".gpth-progress.json";
 
+/// Append-only journal of files written since the last checkpoint save,
+/// one JSON `WrittenFile` per line
+pub const JOURNAL_FILENAME: &str = ".gpth-progress.log";
+
 /// A file that was successfully written to the output directory.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WrittenFile {
@@ -26,104 +36,331 @@ pub struct WrittenFile {
     pub size: u64,
 }
 
+/// Export timestamp and part number in a Takeout file name,
+/// e.g. takeout-20240101T000000Z-001.zip or takeout-20240101T000000Z-3-001.zip
+static ARCHIVE_ID_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)takeout-(\d{8}T\d{6}Z(?:-\d+)+)").unwrap());
+
+/// Identity of an input ZIP that survives copying it to another disk or
+/// renaming it, but not changes to its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZipFingerprint {
+    pub size: u64,
+    /// SHA-256 of everything from the start of the central directory to the
+    /// end of the file: every entry's name, sizes and CRC-32
+    pub cd_hash: String,
+    /// Takeout archive ID from the file name, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_id: Option<String>,
+}
+
+impl ZipFingerprint {
+    /// Fingerprint the ZIP at `path`.
+    pub fn of(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(Error::zip(path))?;
+        let size = file.metadata().map_err(Error::zip(path))?.len();
+        let cd_start = ZipArchive::new(BufReader::new(&file))
+            .map_err(Error::zip(path))?
+            .central_directory_start();
+        let mut reader = BufReader::new(&file);
+        let mut hasher = Sha256::new();
+        reader
+            .seek(SeekFrom::Start(cd_start))
+            .and_then(|_| std::io::copy(&mut reader, &mut hasher))
+            .map_err(Error::zip(path))?;
+        let archive_id = path
+            .file_name()
+            .and_then(|n| ARCHIVE_ID_RE.captures(&n.to_string_lossy()).map(|c| c[1].to_string()));
+        Ok(Self {
+            size,
+            cd_hash: hex::encode(hasher.finalize()),
+            archive_id,
+        })
+    }
+
+    /// Same archive: size and central directory are identical, and so are
+    /// the archive IDs when both file names carry one.
+    pub fn matches(&self, other: &Self) -> bool {
+        self.size == other.size
+            && self.cd_hash == other.cd_hash
+            && match (&self.archive_id, &other.archive_id) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+    }
+}
+
+/// Fingerprints for all zip files.
+pub(crate) fn get_zip_fingerprints(zip_files: &[String]) -> Result<Vec<ZipFingerprint>> {
+    zip_files.iter().map(|path| ZipFingerprint::of(Path::new(path))).collect()
+}
+
+/// Dedup counts carried over to the result of a resumed run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DedupSummary {
+    pub total_media: u64,
+    pub duplicates_removed: u64,
+    pub warnings: Vec<String>,
+}
+
+/// Where a resumed run picks up, with the media list saved by the last
+/// completed stage.
+#[derive(Debug, Clone)]
+pub enum ResumePoint {
+    /// Scan and dates are done; media carry dates and album entries
+    Dedup(Vec<Media>),
+    /// Dedup is done too; media carry hashes and merged album memberships
+    Write(Vec<Media>, DedupSummary),
+}
+
+/// Why a checkpointed output file no longer counts as written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DriftReason {
+    Missing,
+    SizeChanged { expected: u64, actual: u64 },
+    ContentChanged,
+}
+
+impl std::fmt::Display for DriftReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriftReason::Missing => write!(f, "missing"),
+            DriftReason::SizeChanged { expected, actual } => {
+                write!(f, "size changed ({} bytes, expected {})", actual, expected)
+            }
+            DriftReason::ContentChanged => write!(f, "content changed"),
+        }
+    }
+}
+
+/// A file the checkpoint records as written that was deleted or modified
+/// before the run resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftedFile {
+    pub zip_path: String,
+    pub output_path: PathBuf,
+    pub reason: DriftReason,
+}
+
+/// Whether a run with different options can pick up a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Different ZIPs, a finished run or an older format: start fresh
+    Incompatible,
+    /// Files written so far can be kept
+    Compatible {
+        /// Found media or their dates differ, so scan, dates and dedup rerun
+        media_changed: bool,
+        /// Output paths differ, so written files must move to be kept
+        paths_changed: bool,
+    },
+}
+
 /// Checkpoint data stored in .gpth-progress.json
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Checkpoint {
+    /// Checkpoints of an older version load (fields they lack take their
+    /// defaults) but are never compatible, so the run starts fresh
     pub version: u32,
     pub timestamp: DateTime<Utc>,
-    pub options_hash: String,
+    /// Hash of the options that decide where files are written
+    #[serde(default)]
+    pub path_options_hash: String,
+    /// Hash of the options that decide which media are found and their dates
+    #[serde(default)]
+    pub media_options_hash: String,
+    /// Paths the ZIPs had when the checkpoint was saved; informational only,
+    /// inputs are matched by `zip_fingerprints`
     pub zip_files: Vec<String>,
-    pub zip_mtimes: Vec<i64>,
+    #[serde(default)]
+    pub zip_fingerprints: Vec<ZipFingerprint>,
     pub written_files: Vec<WrittenFile>,
     pub last_stage: String,
     pub completed: bool,
+    /// Content hash algorithm used by this run
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// Files that failed in the last run; they are retried on resume
+    #[serde(default)]
+    pub failed_items: Vec<FailedItem>,
+    /// Media list as of the last completed stage
+    #[serde(default)]
+    pub media: Vec<Media>,
+    /// Set once dedup has completed
+    #[serde(default)]
+    pub dedup: Option<DedupSummary>,
+    /// Written files are still at the paths of earlier options and move to
+    /// their new paths in the write stage
+    #[serde(default)]
+    pub migrate_pending: bool,
 }
 
 impl Checkpoint {
     /// Create a new checkpoint for the given options.
-    pub fn new(options: &ProcessOptions) -> anyhow::Result<Self> {
-        let options_hash = compute_options_hash(options);
-        let zip_mtimes = get_zip_mtimes(&options.zip_files)?;
+    pub fn new(options: &ProcessOptions) -> Result<Self> {
+        Ok(Self::with_fingerprints(options, get_zip_fingerprints(&options.zip_files)?))
+    }
 
-        Ok(Self {
+    /// `new` for ZIPs the caller has already fingerprinted.
+    pub(crate) fn with_fingerprints(options: &ProcessOptions, zip_fingerprints: Vec<ZipFingerprint>) -> Self {
+        Self {
             version: CHECKPOINT_VERSION,
             timestamp: Utc::now(),
-            options_hash,
+            path_options_hash: path_options_hash(options),
+            media_options_hash: media_options_hash(options),
             zip_files: options.zip_files.clone(),
-            zip_mtimes,
+            zip_fingerprints,
             written_files: Vec::new(),
             last_stage: String::new(),
             completed: false,
-        })
+            hash_algorithm: options.hash_algorithm,
+            failed_items: Vec::new(),
+            media: Vec::new(),
+            dedup: None,
+            migrate_pending: false,
+        }
     }
 
-    /// Load checkpoint from output directory.
-    pub fn load(output_dir: &Path) -> anyhow::Result<Option<Self>> {
+    /// Load checkpoint from output directory, replaying the journal of files
+    /// written after it was saved.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
         let path = output_dir.join(CHECKPOINT_FILENAME);
         if !path.exists() {
             return Ok(None);
         }
 
-        let file = File::open(&path)?;
+        let file = File::open(&path).map_err(Error::io(&path))?;
         let reader = BufReader::new(file);
-        let checkpoint: Checkpoint = serde_json::from_reader(reader)?;
+        let mut checkpoint: Checkpoint =
+            serde_json::from_reader(reader).map_err(|e| Error::IncompatibleCheckpoint {
+                path: path.clone(),
+                reason: e.to_string(),
+            })?;
+        let journal = output_dir.join(JOURNAL_FILENAME);
+        checkpoint.replay_journal(&journal).map_err(Error::io(&journal))?;
 
         Ok(Some(checkpoint))
     }
 
-    /// Save checkpoint to output directory.
-    pub fn save(&self, output_dir: &Path) -> anyhow::Result<()> {
+    /// Append journal entries to `written_files`. A crash can leave the last
+    /// line half-written; reading stops there. Entries already in the
+    /// checkpoint (saved just before the journal was removed) are ignored.
+    fn replay_journal(&mut self, path: &Path) -> std::io::Result<()> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut seen: HashSet<(String, PathBuf)> = self
+            .written_files
+            .iter()
+            .map(|f| (f.zip_path.clone(), f.output_path.clone()))
+            .collect();
+        for line in BufReader::new(file).lines() {
+            let Ok(entry) = serde_json::from_str::<WrittenFile>(&line?) else {
+                break;
+            };
+            if seen.insert((entry.zip_path.clone(), entry.output_path.clone())) {
+                self.written_files.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the whole checkpoint to output directory. The journal is folded
+    /// into it and removed.
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
         let path = output_dir.join(CHECKPOINT_FILENAME);
         let temp_path = output_dir.join(".gpth-progress.tmp");
 
         // Write to temp file first, then rename for atomicity
-        let file = File::create(&temp_path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, self)?;
-
-        fs::rename(&temp_path, &path)?;
+        let file = File::create(&temp_path).map_err(Error::io(&temp_path))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.flush())
+            .map_err(Error::io(&temp_path))?;
+
+        fs::rename(&temp_path, &path).map_err(Error::io(&path))?;
+        remove_if_exists(&output_dir.join(JOURNAL_FILENAME))?;
         Ok(())
     }
 
-    /// Delete checkpoint file from output directory.
-    pub fn delete(output_dir: &Path) -> anyhow::Result<()> {
-        let path = output_dir.join(CHECKPOINT_FILENAME);
-        if path.exists() {
-            fs::remove_file(&path)?;
-        }
-        Ok(())
+    /// Delete checkpoint and journal from output directory.
+    pub fn delete(output_dir: &Path) -> Result<()> {
+        remove_if_exists(&output_dir.join(CHECKPOINT_FILENAME))?;
+        remove_if_exists(&output_dir.join(JOURNAL_FILENAME))
+    }
+
+    /// Check if this checkpoint can be resumed with the given options as is,
+    /// without moving written files.
+    pub fn is_compatible(&self, options: &ProcessOptions) -> Result<bool> {
+        Ok(matches!(
+            self.compatibility(options)?,
+            Compatibility::Compatible { paths_changed: false, .. }
+        ))
+    }
+
+    /// Check how far this checkpoint can be reused with the given options.
+    pub fn compatibility(&self, options: &ProcessOptions) -> Result<Compatibility> {
+        Ok(self.compatibility_with(options, &get_zip_fingerprints(&options.zip_files)?))
     }
 
-    /// Check if this checkpoint is compatible with the given options.
-    pub fn is_compatible(&self, options: &ProcessOptions) -> anyhow::Result<bool> {
+    /// `compatibility` against already fingerprinted input ZIPs.
+    pub(crate) fn compatibility_with(&self, options: &ProcessOptions, current: &[ZipFingerprint]) -> Compatibility {
         // Version check
         if self.version != CHECKPOINT_VERSION {
-            return Ok(false);
+            return Compatibility::Incompatible;
         }
 
         // Already completed
         if self.completed {
-            return Ok(false);
+            return Compatibility::Incompatible;
         }
 
-        // Options hash must match
-        let current_hash = compute_options_hash(options);
-        if self.options_hash != current_hash {
-            return Ok(false);
+        // Same ZIPs in the same order, wherever they are now and whatever
+        // they are called; contents must not have changed
+        if self.zip_fingerprints.len() != current.len()
+            || !self.zip_fingerprints.iter().zip(current).all(|(a, b)| a.matches(b))
+        {
+            return Compatibility::Incompatible;
         }
 
-        // Zip files must match
-        if self.zip_files != options.zip_files {
-            return Ok(false);
+        Compatibility::Compatible {
+            media_changed: self.media_options_hash != media_options_hash(options),
+            paths_changed: self.path_options_hash != path_options_hash(options),
         }
+    }
 
-        // Zip mtimes must match (files haven't been modified)
-        let current_mtimes = get_zip_mtimes(&options.zip_files)?;
-        if self.zip_mtimes != current_mtimes {
-            return Ok(false);
-        }
+    /// Take over changed options: stage outputs are dropped when the media
+    /// they describe changed, and written files are queued to move when
+    /// their paths changed.
+    pub fn adopt_options(&mut self, options: &ProcessOptions) -> Result<()> {
+        self.adopt_options_with(options, &get_zip_fingerprints(&options.zip_files)?)
+    }
 
-        Ok(true)
+    /// `adopt_options` for already fingerprinted input ZIPs.
+    pub(crate) fn adopt_options_with(&mut self, options: &ProcessOptions, current: &[ZipFingerprint]) -> Result<()> {
+        let Compatibility::Compatible { media_changed, paths_changed } = self.compatibility_with(options, current) else {
+            return Err(Error::IncompatibleCheckpoint {
+                path: options.output.join(CHECKPOINT_FILENAME),
+                reason: "it does not match the input ZIPs".to_string(),
+            });
+        };
+        if media_changed {
+            self.media.clear();
+            self.dedup = None;
+            self.last_stage.clear();
+            self.media_options_hash = media_options_hash(options);
+        }
+        if paths_changed {
+            self.migrate_pending = true;
+            self.path_options_hash = path_options_hash(options);
+        }
+        self.zip_files = options.zip_files.clone();
+        self.timestamp = Utc::now();
+        Ok(())
     }
 
     /// Mark a file as successfully written.
@@ -150,6 +387,35 @@ impl Checkpoint {
         self.timestamp = Utc::now();
     }
 
+    /// Record the dated media list; the next run resumes at dedup.
+    pub fn record_dates(&mut self, media: &[Media]) {
+        self.media = media.to_vec();
+        self.dedup = None;
+        self.set_stage("dedup");
+    }
+
+    /// Record the deduplicated media list; the next run resumes at write.
+    pub fn record_dedup(&mut self, media: &[Media], summary: DedupSummary) {
+        self.media = media.to_vec();
+        self.dedup = Some(summary);
+        self.set_stage("write");
+    }
+
+    /// Stage a resumed run can start from, if an earlier run got past dates.
+    /// Hashes are only reused when computed with `hash_algorithm`.
+    pub fn resume_point(&self, hash_algorithm: HashAlgorithm) -> Option<ResumePoint> {
+        if self.media.is_empty() {
+            return None;
+        }
+        match (self.last_stage.as_str(), &self.dedup) {
+            ("dedup", _) => Some(ResumePoint::Dedup(self.media.clone())),
+            ("write", Some(summary)) if self.hash_algorithm == hash_algorithm => {
+                Some(ResumePoint::Write(self.media.clone(), summary.clone()))
+            }
+            _ => None,
+        }
+    }
+
     /// Mark processing as completed.
     pub fn mark_completed(&mut self) {
         self.completed = true;
@@ -157,33 +423,56 @@ impl Checkpoint {
     }
 }
 
-/// Compute a hash of the relevant options for compatibility checking.
-fn compute_options_hash(options: &ProcessOptions) -> String {
+fn file_crc32(path: &Path) -> std::io::Result<u32> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        hasher.update(buf);
+        let n = buf.len();
+        reader.consume(n);
+    }
+    Ok(hasher.finalize())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::io(path)(e)),
+    }
+}
+
+/// Hash of the options that decide where each file is written. Changing
+/// one of them needs a migration to keep written files. `no_guess` is here
+/// because dates pick the year/month folder; `album_dest` and `album_link`
+/// decide where album entries go and whether they are copies or links.
+fn path_options_hash(options: &ProcessOptions) -> String {
     let mut hasher = Sha256::new();
-    // Include options that affect output
     hasher.update(if options.divide_to_dates { b"1" } else { b"0" });
-    hasher.update(if options.skip_extras { b"1" } else { b"0" });
     hasher.update(if options.no_guess { b"1" } else { b"0" });
-    hasher.update(if options.albums { b"1" } else { b"0" });
     hasher.update(options.album_dest.as_bytes());
     hasher.update(if options.album_link { b"1" } else { b"0" });
+    hasher.update(options.name_profile.as_str().as_bytes());
+    hasher.update(options.case_sensitivity.as_str().as_bytes());
+    hasher.update(options.normalization.as_str().as_bytes());
     hasher.update(options.output.to_string_lossy().as_bytes());
     hex::encode(hasher.finalize())
 }
 
-/// Get modification times for all zip files.
-fn get_zip_mtimes(zip_files: &[String]) -> anyhow::Result<Vec<i64>> {
-    let mut mtimes = Vec::with_capacity(zip_files.len());
-    for path in zip_files {
-        let metadata = fs::metadata(path)?;
-        let mtime = metadata
-            .modified()?
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .unwrap_or(0);
-        mtimes.push(mtime);
-    }
-    Ok(mtimes)
+/// Hash of the options that decide which media the scan finds and how they
+/// are dated. Changing one reruns the stages before writing but keeps
+/// written files. Options in neither hash (`album_json`,
+/// `on_conflict`, durability) can change freely between runs.
+fn media_options_hash(options: &ProcessOptions) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(if options.skip_extras { b"1" } else { b"0" });
+    hasher.update(if options.no_guess { b"1" } else { b"0" });
+    hasher.update(if options.albums { b"1" } else { b"0" });
+    hex::encode(hasher.finalize())
 }
 
 /// Token for cooperative cancellation and pause support.
@@ -191,6 +480,7 @@ fn get_zip_mtimes(zip_files: &[String]) -> anyhow::Result<Vec<i64>> {
 pub struct CancellationToken {
     cancelled: Arc<AtomicBool>,
     paused: Arc<AtomicBool>,
+    space_paused: Arc<AtomicBool>,
 }
 
 impl Default for CancellationToken {
@@ -205,6 +495,7 @@ impl CancellationToken {
         Self {
             cancelled: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
+            space_paused: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -228,6 +519,17 @@ impl CancellationToken {
         self.paused.load(Ordering::SeqCst)
     }
 
+    /// Set paused state because the output filesystem is low on space.
+    /// Kept separate from `set_paused` so a user pause outlives space recovery.
+    pub fn set_space_paused(&self, paused: bool) {
+        self.space_paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// Check if paused for low disk space.
+    pub fn is_space_paused(&self) -> bool {
+        self.space_paused.load(Ordering::SeqCst)
+    }
+
     /// Check cancellation and wait while paused.
     /// Returns Ok(()) to continue, Err if cancelled.
     pub fn check(&self) -> Result<(), CancelledError> {
@@ -236,7 +538,7 @@ impl CancellationToken {
         }
 
         // Wait while paused
-        while self.is_paused() {
+        while self.is_paused() || self.is_space_paused() {
             if self.is_cancelled() {
                 return Err(CancelledError);
             }
@@ -259,10 +561,18 @@ impl std::fmt::Display for CancelledError {
 
 impl std::error::Error for CancelledError {}
 
-/// Manages checkpoint saving with throttling to reduce I/O overhead.
+/// Manages checkpoint saving. Written files are appended to the journal, so
+/// marking one costs the same however many came before; the journal is
+/// flushed to disk with throttling to reduce I/O overhead, and folded into
+/// the checkpoint file only on stage changes, cancellation and failure.
 pub struct CheckpointSaver {
     checkpoint: Checkpoint,
     output_dir: PathBuf,
+    journal: Option<BufWriter<File>>,
+    /// Files dropped by `validate_written`, to be written again in place
+    requeued: HashSet<PathBuf>,
+    /// Saved since the last `report_saved`
+    saved: bool,
     last_save: Instant,
     files_since_save: usize,
     min_interval: Duration,
@@ -275,6 +585,9 @@ impl CheckpointSaver {
         Self {
             checkpoint,
             output_dir,
+            journal: None,
+            requeued: HashSet::new(),
+            saved: false,
             last_save: Instant::now(),
             files_since_save: 0,
             min_interval: Duration::from_secs(5),
@@ -283,40 +596,165 @@ impl CheckpointSaver {
     }
 
     /// Create a checkpoint saver for resuming from an existing checkpoint.
+    /// The replayed journal is folded in right away, dropping any line a
+    /// crash left half-written before new entries are appended after it.
     pub fn from_existing(checkpoint: Checkpoint, output_dir: PathBuf) -> Self {
-        Self::new(checkpoint, output_dir)
+        let mut saver = Self::new(checkpoint, output_dir);
+        saver.force_save();
+        saver
     }
 
-    /// Mark a file as written and maybe save checkpoint.
+    /// Mark a file as written, appending it to the journal.
     pub fn mark_written(&mut self, zip_path: &str, output_path: &Path, size: u64) {
         self.checkpoint.mark_written(zip_path, output_path, size);
+        if let Some(entry) = self.checkpoint.written_files.last().cloned() {
+            let _ = self.append_journal(&entry);
+        }
         self.files_since_save += 1;
         self.maybe_save();
     }
 
-    /// Save checkpoint if enough time has passed or enough files processed.
+    fn append_journal(&mut self, entry: &WrittenFile) -> anyhow::Result<()> {
+        if self.journal.is_none() {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.output_dir.join(JOURNAL_FILENAME))?;
+            self.journal = Some(BufWriter::new(file));
+        }
+        let Some(journal) = self.journal.as_mut() else {
+            return Ok(());
+        };
+        serde_json::to_writer(&mut *journal, entry)?;
+        journal.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flush the journal if enough time has passed or enough files processed.
     fn maybe_save(&mut self) {
         let should_save = self.last_save.elapsed() >= self.min_interval
             || self.files_since_save >= self.min_files;
         if should_save {
-            self.force_save();
+            if let Some(journal) = &mut self.journal {
+                let _ = journal.flush().and_then(|()| journal.get_ref().sync_data());
+            }
+            self.saved = true;
+            self.last_save = Instant::now();
+            self.files_since_save = 0;
         }
     }
 
-    /// Force save checkpoint to disk.
+    /// Force save the whole checkpoint to disk, folding in the journal.
     pub fn force_save(&mut self) {
+        if let Some(mut journal) = self.journal.take() {
+            let _ = journal.flush();
+        }
         let _ = self.checkpoint.save(&self.output_dir);
+        self.saved = true;
         self.last_save = Instant::now();
         self.files_since_save = 0;
     }
 
+    /// Emit a `CheckpointSaved` event if the checkpoint or journal was saved
+    /// since the last call.
+    pub fn report_saved(&mut self, progress: &ThrottledProgress) {
+        if std::mem::take(&mut self.saved) {
+            progress.checkpoint_saved(self.checkpoint.written_files.len() as u64);
+        }
+    }
+
+    /// Check the files recorded as written against the output directory:
+    /// each must exist with its recorded size and, with `check_content`, the
+    /// CRC-32 of its ZIP entry. Drifted files are dropped from the checkpoint
+    /// so the write stage writes them again, and returned for reporting.
+    pub fn validate_written(&mut self, media: &[Media], zip_files: &[String], check_content: bool) -> Vec<DriftedFile> {
+        let by_zip_path: HashMap<&str, &Media> = media.iter().map(|m| (m.zip_path.as_str(), m)).collect();
+        let mut archives: HashMap<usize, Option<ZipArchive<File>>> = HashMap::new();
+        let mut drifted = Vec::new();
+        for f in &self.checkpoint.written_files {
+            let reason = match fs::metadata(&f.output_path) {
+                Err(_) => Some(DriftReason::Missing),
+                Ok(meta) if meta.len() != f.size => Some(DriftReason::SizeChanged {
+                    expected: f.size,
+                    actual: meta.len(),
+                }),
+                Ok(_) if check_content => by_zip_path
+                    .get(f.zip_path.as_str())
+                    .and_then(|m| {
+                        let archive = archives.entry(m.zip_index).or_insert_with(|| {
+                            File::open(&zip_files[m.zip_index]).ok().and_then(|f| ZipArchive::new(f).ok())
+                        });
+                        let expected = archive.as_mut()?.by_index_raw(m.entry_index).ok()?.crc32();
+                        let actual = file_crc32(&f.output_path).ok()?;
+                        (actual != expected).then_some(DriftReason::ContentChanged)
+                    }),
+                Ok(_) => None,
+            };
+            if let Some(reason) = reason {
+                drifted.push(DriftedFile {
+                    zip_path: f.zip_path.clone(),
+                    output_path: f.output_path.clone(),
+                    reason,
+                });
+            }
+        }
+
+        if !drifted.is_empty() {
+            self.requeued = drifted.iter().map(|d| d.output_path.clone()).collect();
+            let requeued = &self.requeued;
+            self.checkpoint.written_files.retain(|f| !requeued.contains(&f.output_path));
+            self.force_save();
+        }
+        drifted
+    }
+
+    /// With a migration pending, hand over the written files (zip path ->
+    /// old output path) and forget them; the write stage records each again
+    /// at its new path, or writes it anew if it cannot be moved.
+    pub fn take_migration(&mut self) -> Option<HashMap<String, PathBuf>> {
+        if !self.checkpoint.migrate_pending {
+            return None;
+        }
+        let written = self.checkpoint.get_written_map();
+        self.checkpoint.written_files.clear();
+        self.checkpoint.migrate_pending = false;
+        Some(written)
+    }
+
+    /// Output files that `validate_written` found changed; they are
+    /// overwritten rather than treated as existing files.
+    pub fn requeued(&self) -> &HashSet<PathBuf> {
+        &self.requeued
+    }
+
     /// Set the current stage.
     pub fn set_stage(&mut self, stage: &str) {
         self.checkpoint.set_stage(stage);
     }
 
-    /// Mark as completed and delete checkpoint file.
-    pub fn mark_completed(&mut self) -> anyhow::Result<()> {
+    /// Save the dated media list so a resumed run skips scan and dates.
+    pub fn record_dates(&mut self, media: &[Media]) {
+        self.checkpoint.record_dates(media);
+        self.force_save();
+    }
+
+    /// Save the deduplicated media list so a resumed run goes straight to writing.
+    pub fn record_dedup(&mut self, media: &[Media], summary: DedupSummary) {
+        self.checkpoint.record_dedup(media, summary);
+        self.force_save();
+    }
+
+    /// Record the files that failed and save, keeping the checkpoint so the
+    /// next run resumes and retries only those files.
+    pub fn mark_failed(&mut self, failed: &[FailedItem]) {
+        self.checkpoint.failed_items = failed.to_vec();
+        self.checkpoint.timestamp = Utc::now();
+        self.force_save();
+    }
+
+    /// Mark as completed and delete checkpoint and journal.
+    pub fn mark_completed(&mut self) -> Result<()> {
+        self.journal = None;
         self.checkpoint.mark_completed();
         Checkpoint::delete(&self.output_dir)
     }
@@ -340,19 +778,19 @@ mod tests {
 
     fn test_options() -> ProcessOptions {
         ProcessOptions {
-            zip_files: vec!["test.zip".to_string()],
-            output: PathBuf::from("/tmp/output"),
             divide_to_dates: true,
-            skip_extras: false,
-            no_guess: false,
-            albums: false,
-            album_dest: "year".to_string(),
-            album_link: false,
-            album_json: None,
-            force: false,
+            ..ProcessOptions::for_test(Path::new("test.zip"), Path::new("/tmp/output"))
         }
     }
 
+    fn write_zip(path: &Path, data: &[u8]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        zip.start_file("Takeout/Google Photos/Photos from 2023/a.jpg", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(data).unwrap();
+        zip.finish().unwrap();
+    }
+
     #[test]
     fn test_cancellation_token() {
         let token = CancellationToken::new();
@@ -370,21 +808,13 @@ mod tests {
         let dir = tempdir().unwrap();
         let dir_path = dir.path();
 
-        // Create a dummy zip file for mtime
+        // Create a small zip file to fingerprint
         let zip_path = dir_path.join("test.zip");
-        File::create(&zip_path).unwrap().write_all(b"test").unwrap();
+        write_zip(&zip_path, b"test");
 
         let options = ProcessOptions {
-            zip_files: vec![zip_path.to_string_lossy().to_string()],
-            output: dir_path.to_path_buf(),
             divide_to_dates: true,
-            skip_extras: false,
-            no_guess: false,
-            albums: false,
-            album_dest: "year".to_string(),
-            album_link: false,
-            album_json: None,
-            force: false,
+            ..ProcessOptions::for_test(&zip_path, dir_path)
         };
 
         let mut checkpoint = Checkpoint::new(&options).unwrap();
@@ -398,13 +828,183 @@ mod tests {
     }
 
     #[test]
-    fn test_options_hash_changes() {
-        let opts1 = test_options();
-        let mut opts2 = test_options();
-        opts2.divide_to_dates = false;
-
-        let hash1 = compute_options_hash(&opts1);
-        let hash2 = compute_options_hash(&opts2);
-        assert_ne!(hash1, hash2);
+    fn test_resume_point() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        write_zip(&zip_path, b"test");
+        let mut options = test_options();
+        options.zip_files = vec![zip_path.to_string_lossy().to_string()];
+        options.output = dir.path().to_path_buf();
+
+        let mut checkpoint = Checkpoint::new(&options).unwrap();
+        checkpoint.set_stage("date");
+        assert!(checkpoint.resume_point(HashAlgorithm::Sha256).is_none());
+
+        let mut m = Media::new("Photos/a.jpg".to_string(), 0, 3, "a.jpg".to_string(), 10);
+        m.date_accuracy = 1;
+        checkpoint.record_dates(std::slice::from_ref(&m));
+        checkpoint.save(dir.path()).unwrap();
+        let loaded = Checkpoint::load(dir.path()).unwrap().unwrap();
+        match loaded.resume_point(HashAlgorithm::Sha256) {
+            Some(ResumePoint::Dedup(media)) => assert_eq!(media[0].entry_index, 3),
+            other => panic!("unexpected resume point {:?}", other),
+        }
+
+        m.hash = Some("abc".to_string());
+        let summary = DedupSummary {
+            total_media: 2,
+            duplicates_removed: 1,
+            warnings: vec![],
+        };
+        checkpoint.record_dedup(&[m], summary);
+        checkpoint.save(dir.path()).unwrap();
+        let loaded = Checkpoint::load(dir.path()).unwrap().unwrap();
+        match loaded.resume_point(HashAlgorithm::Sha256) {
+            Some(ResumePoint::Write(media, summary)) => {
+                assert_eq!(media[0].hash.as_deref(), Some("abc"));
+                assert_eq!(summary.duplicates_removed, 1);
+            }
+            other => panic!("unexpected resume point {:?}", other),
+        }
+        // Hashes from another algorithm are not reused
+        assert!(loaded.resume_point(HashAlgorithm::Blake3).is_none());
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        write_zip(&zip_path, b"test");
+        let mut options = test_options();
+        options.zip_files = vec![zip_path.to_string_lossy().to_string()];
+        options.output = dir.path().to_path_buf();
+
+        let mut saver = CheckpointSaver::new(Checkpoint::new(&options).unwrap(), dir.path().to_path_buf());
+        saver.set_stage("write");
+        saver.force_save();
+        saver.mark_written("Photos/a.jpg", Path::new("a.jpg"), 1);
+        saver.mark_written("Photos/b.jpg", Path::new("b.jpg"), 2);
+        drop(saver);
+
+        // Simulate a crash in the middle of appending a line
+        let journal_path = dir.path().join(JOURNAL_FILENAME);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&journal_path)
+            .unwrap()
+            .write_all(b"{\"zip_path\":\"Photos/c")
+            .unwrap();
+
+        let loaded = Checkpoint::load(dir.path()).unwrap().unwrap();
+        assert_eq!(loaded.written_files.len(), 2);
+        assert_eq!(loaded.last_stage, "write");
+
+        // Resuming folds the journal into the checkpoint file
+        let mut saver = CheckpointSaver::from_existing(loaded, dir.path().to_path_buf());
+        assert!(!journal_path.exists());
+        saver.mark_written("Photos/c.jpg", Path::new("c.jpg"), 3);
+        drop(saver);
+        assert_eq!(Checkpoint::load(dir.path()).unwrap().unwrap().written_files.len(), 3);
+    }
+
+    #[test]
+    fn test_moved_zip_stays_compatible() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout-20240101T000000Z-001.zip");
+        write_zip(&zip_path, b"test");
+        let mut options = test_options();
+        options.zip_files = vec![zip_path.to_string_lossy().to_string()];
+        options.output = dir.path().join("out");
+        let checkpoint = Checkpoint::new(&options).unwrap();
+        assert_eq!(checkpoint.zip_fingerprints[0].archive_id.as_deref(), Some("20240101T000000Z-001"));
+
+        // Moved to another directory under a name without the archive ID
+        fs::create_dir(dir.path().join("copy")).unwrap();
+        let moved = dir.path().join("copy/part1.zip");
+        fs::rename(&zip_path, &moved).unwrap();
+        options.zip_files = vec![moved.to_string_lossy().to_string()];
+        assert!(checkpoint.is_compatible(&options).unwrap());
+
+        // Another part of the same export has a different archive ID
+        let other_part = dir.path().join("copy/takeout-20240101T000000Z-002.zip");
+        fs::rename(&moved, &other_part).unwrap();
+        options.zip_files = vec![other_part.to_string_lossy().to_string()];
+        assert!(!checkpoint.is_compatible(&options).unwrap());
+
+        // Same name and size, different contents
+        let renamed_back = dir.path().join("copy/takeout-20240101T000000Z-001.zip");
+        fs::rename(&other_part, &renamed_back).unwrap();
+        options.zip_files = vec![renamed_back.to_string_lossy().to_string()];
+        assert!(checkpoint.is_compatible(&options).unwrap());
+        write_zip(&renamed_back, b"tesT");
+        assert!(!checkpoint.is_compatible(&options).unwrap());
+    }
+
+    #[test]
+    fn test_option_changes() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        write_zip(&zip_path, b"test");
+        let mut options = test_options();
+        options.zip_files = vec![zip_path.to_string_lossy().to_string()];
+        let mut checkpoint = Checkpoint::new(&options).unwrap();
+        checkpoint.record_dates(&[Media::new("Photos/a.jpg".to_string(), 0, 0, "a.jpg".to_string(), 4)]);
+
+        let compatible = |media_changed, paths_changed| Compatibility::Compatible {
+            media_changed,
+            paths_changed,
+        };
+        let mut changed = options.clone();
+        changed.album_json = Some(PathBuf::from("/tmp/albums.json"));
+        changed.on_conflict = crate::writer::ConflictPolicy::Skip;
+        assert_eq!(checkpoint.compatibility(&changed).unwrap(), compatible(false, false));
+        changed.skip_extras = true;
+        assert_eq!(checkpoint.compatibility(&changed).unwrap(), compatible(true, false));
+        assert!(checkpoint.is_compatible(&changed).unwrap());
+        changed.divide_to_dates = false;
+        assert_eq!(checkpoint.compatibility(&changed).unwrap(), compatible(true, true));
+        assert!(!checkpoint.is_compatible(&changed).unwrap());
+
+        checkpoint.adopt_options(&changed).unwrap();
+        assert!(checkpoint.migrate_pending);
+        assert!(checkpoint.resume_point(HashAlgorithm::Sha256).is_none());
+        assert_eq!(checkpoint.compatibility(&changed).unwrap(), compatible(false, false));
+
+        // Album placement moves album files
+        let mut album_changed = changed.clone();
+        album_changed.album_dest = "album".to_string();
+        assert_eq!(checkpoint.compatibility(&album_changed).unwrap(), compatible(false, true));
+        album_changed.album_dest = changed.album_dest.clone();
+        album_changed.album_link = !changed.album_link;
+        assert_eq!(checkpoint.compatibility(&album_changed).unwrap(), compatible(false, true));
+    }
+
+    #[test]
+    fn test_older_checkpoint_versions_start_fresh() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        write_zip(&zip_path, b"test");
+        let mut options = test_options();
+        options.zip_files = vec![zip_path.to_string_lossy().to_string()];
+        let zip_file = serde_json::to_string(&options.zip_files[0]).unwrap();
+        let fingerprint = serde_json::to_string(&ZipFingerprint::of(&zip_path).unwrap()).unwrap();
+
+        // v1: options hash and ZIP mtimes; v2: ZIP fingerprints, one options hash
+        let v1 = format!(
+            r#"{{"version":1,"timestamp":"2024-01-01T00:00:00Z","options_hash":"abc","zip_files":[{zip_file}],
+            "zip_mtimes":[1704067200],"written_files":[{{"zip_path":"Photos/a.jpg","output_path":"2023/01/a.jpg","size":4}}],
+            "last_stage":"write","completed":false}}"#
+        );
+        let v2 = format!(
+            r#"{{"version":2,"timestamp":"2024-01-01T00:00:00Z","options_hash":"abc","zip_files":[{zip_file}],
+            "zip_fingerprints":[{fingerprint}],"written_files":[],"last_stage":"write","completed":false,
+            "hash_algorithm":"sha256","failed_items":[],"media":[],"dedup":null}}"#
+        );
+        for (version, json) in [(1, v1), (2, v2)] {
+            fs::write(dir.path().join(CHECKPOINT_FILENAME), json).unwrap();
+            let loaded = Checkpoint::load(dir.path()).unwrap().unwrap();
+            assert_eq!(loaded.version, version);
+            assert_eq!(loaded.compatibility(&options).unwrap(), Compatibility::Incompatible);
+        }
     }
 }
@@ -23,17 +23,14 @@ pub fn extract_exif_date(bytes: &[u8]) -> Option<NaiveDateTime> {
 
 fn parse_exif_datetime(s: &str) -> Option<NaiveDateTime> {
     let cleaned = s
-        .replace('-', ":")
-        .replace('/', ":")
-        .replace('\\', ":")
-        .replace('.', ":");
+        .replace(['-', '/', '\\', '.'], ":");
 
     if let Ok(dt) = NaiveDateTime::parse_from_str(&cleaned, "%Y:%m:%d %H:%M:%S") {
         return Some(dt);
     }
 
-    if let Ok(d) = chrono::NaiveDate::parse_from_str(&cleaned.split(' ').next()?, "%Y:%m:%d") {
-        return Some(d.and_hms_opt(0, 0, 0)?);
+    if let Ok(d) = chrono::NaiveDate::parse_from_str(cleaned.split(' ').next()?, "%Y:%m:%d") {
+        return d.and_hms_opt(0, 0, 0);
     }
 
     None
@@ -3,24 +3,87 @@ use std::fs::File;
 use std::io::Read;
 use std::sync::atomic::{AtomicU64, Ordering};
 
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use zip::ZipArchive;
 
+use crate::hash_cache::HashCache;
 use crate::media::Media;
-use crate::ThrottledProgress;
+use crate::{Result, Stage, ThrottledProgress};
 
 /// Buffer size for streaming hash (64 KB)
 const HASH_BUFFER_SIZE: usize = 64 * 1024;
 
 /// Result of deduplication
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DedupResult {
     pub media: Vec<Media>,
     pub warnings: Vec<String>,
+    /// Album entries folded into a file with identical content
+    pub album_merged: usize,
 }
 
-/// Compute SHA-256 hash using streaming to avoid loading entire file into memory
-fn compute_streaming_hash<R: Read>(mut reader: R) -> std::io::Result<String> {
-    let mut hasher = Sha256::new();
+/// Content hash algorithm used for deduplication
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256 (default, compatible with earlier versions)
+    #[default]
+    Sha256,
+    /// BLAKE3 (several times faster)
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Name as recorded in the checkpoint, report and albums.json
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Incremental hasher for any supported algorithm
+enum ContentHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ContentHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => ContentHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Sha256(h) => h.update(data),
+            ContentHasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            ContentHasher::Sha256(h) => hex::encode(h.finalize()),
+            ContentHasher::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Compute a content hash using streaming to avoid loading entire file into memory
+pub fn compute_streaming_hash<R: Read>(mut reader: R, algorithm: HashAlgorithm) -> std::io::Result<String> {
+    let mut hasher = ContentHasher::new(algorithm);
     let mut buf = [0u8; HASH_BUFFER_SIZE];
     loop {
         let n = reader.read(&mut buf)?;
@@ -29,12 +92,19 @@ fn compute_streaming_hash<R: Read>(mut reader: R) -> std::io::Result<String> {
         }
         hasher.update(&buf[..n]);
     }
-    Ok(hex::encode(hasher.finalize()))
+    Ok(hasher.finalize_hex())
 }
 
-/// Compute SHA-256 hashes for media that share sizes, then remove duplicates.
+/// Compute content hashes for media that share sizes, then remove duplicates.
 /// Uses streaming hash to minimize memory usage - no file size limit.
-pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &ThrottledProgress) -> anyhow::Result<DedupResult> {
+/// Hashes found in `cache` are reused; newly computed ones are added to it.
+pub fn deduplicate(
+    mut media: Vec<Media>,
+    zip_files: &[String],
+    algorithm: HashAlgorithm,
+    cache: &mut HashCache,
+    progress: &ThrottledProgress,
+) -> Result<DedupResult> {
     let mut warnings = Vec::new();
 
     // Group by size
@@ -44,16 +114,31 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
     }
 
     // Only hash files that share a size with at least one other file (no size limit with streaming)
-    let needs_hash: Vec<usize> = size_groups
+    let mut needs_hash: Vec<usize> = size_groups
         .values()
         .filter(|indices| indices.len() > 1)
         .flatten()
         .copied()
         .collect();
 
+    // Reuse hashes computed by earlier runs over the same ZIPs
+    needs_hash.retain(|&idx| {
+        let m = &mut media[idx];
+        match cache.hash(&zip_files[m.zip_index], m.entry_index) {
+            Some(hash) => {
+                m.hash = Some(hash.to_string());
+                false
+            }
+            None => true,
+        }
+    });
+
     if !needs_hash.is_empty() {
         let total = needs_hash.len() as u64;
+        let total_bytes: u64 = needs_hash.iter().map(|&idx| media[idx].size).sum();
         let counter = AtomicU64::new(0);
+        let bytes_counter = AtomicU64::new(0);
+        progress.start(Stage::Dedup, total);
 
         // Group by zip for efficient reading
         let mut by_zip: HashMap<usize, Vec<usize>> = HashMap::new();
@@ -70,7 +155,7 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
             let zip_path = &zip_files[*zip_idx];
 
             // Split work across threads
-            let chunk_size = (media_indices.len() + num_threads - 1) / num_threads;
+            let chunk_size = media_indices.len().div_ceil(num_threads);
             let chunks: Vec<&[usize]> = media_indices.chunks(chunk_size).collect();
 
             let chunk_results: Vec<(Vec<(usize, String)>, usize)> = std::thread::scope(|s| {
@@ -78,9 +163,8 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
                     .into_iter()
                     .map(|chunk| {
                         let media = &media;
-                        let zip_path = zip_path;
                         let counter = &counter;
-                        let progress = progress;
+                        let bytes_counter = &bytes_counter;
                         s.spawn(move || -> (Vec<(usize, String)>, usize) {
                             let mut results = Vec::new();
                             let mut skipped = 0usize;
@@ -104,15 +188,16 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
                                 let m = &media[midx];
                                 match archive.by_index(m.entry_index) {
                                     Ok(entry) => {
-                                        match compute_streaming_hash(entry) {
+                                        match compute_streaming_hash(entry, algorithm) {
                                             Ok(hash) => results.push((midx, hash)),
                                             Err(_) => skipped += 1,
                                         }
                                     }
                                     Err(_) => skipped += 1,
                                 }
-                                let current = counter.fetch_add(1, Ordering::Relaxed);
-                                progress.report("dedup", current, total, "Hashing duplicates");
+                                let current = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                                let bytes = bytes_counter.fetch_add(m.size, Ordering::Relaxed) + m.size;
+                                progress.report_bytes(Stage::Dedup, current, total, (bytes, total_bytes), "Hashing duplicates");
                             }
                             (results, skipped)
                         })
@@ -128,10 +213,14 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
         }
 
         if skipped_count > 0 {
-            warnings.push(format!("Skipped {} files during dedup hashing", skipped_count));
+            let warning = format!("Skipped {} files during dedup hashing", skipped_count);
+            progress.warning(&warning);
+            warnings.push(warning);
         }
+        progress.finish(Stage::Dedup);
 
         for (idx, hash) in all_hashes {
+            cache.set_hash(&zip_files[media[idx].zip_index], media[idx].entry_index, &hash);
             media[idx].hash = Some(hash);
         }
     }
@@ -148,17 +237,36 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
     }
 
     let mut remove_indices: Vec<usize> = Vec::new();
+    let mut album_merged = 0usize;
     for indices in hash_groups.values() {
         if indices.len() <= 1 {
             continue;
         }
+        // Prefer year-folder files, then the best date, then the shortest name
         let mut sorted = indices.clone();
         sorted.sort_by(|&a, &b| {
             media[a]
-                .date_accuracy
-                .cmp(&media[b].date_accuracy)
+                .album_only
+                .cmp(&media[b].album_only)
+                .then_with(|| media[a].date_accuracy.cmp(&media[b].date_accuracy))
                 .then_with(|| media[a].filename.len().cmp(&media[b].filename.len()))
         });
+        let keep = sorted[0];
+        for &dup in &sorted[1..] {
+            if media[dup].album_only {
+                album_merged += 1;
+            }
+            if media[dup].date_accuracy < media[keep].date_accuracy {
+                media[keep].date = media[dup].date;
+                media[keep].date_accuracy = media[dup].date_accuracy;
+            }
+            let albums = std::mem::take(&mut media[dup].albums);
+            for album in albums {
+                if !media[keep].albums.contains(&album) {
+                    media[keep].albums.push(album);
+                }
+            }
+        }
         remove_indices.extend_from_slice(&sorted[1..]);
     }
 
@@ -168,5 +276,73 @@ pub fn deduplicate(mut media: Vec<Media>, zip_files: &[String], progress: &Throt
         media.swap_remove(idx);
     }
 
-    Ok(DedupResult { media, warnings })
+    Ok(DedupResult { media, warnings, album_merged })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    fn build_zip(path: &std::path::Path, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_album_entries_merged_by_content() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        build_zip(
+            &zip_path,
+            &[
+                ("Takeout/Google Photos/Photos from 2023/IMG_1.jpg", b"aaaa"),
+                ("Takeout/Google Photos/Photos from 2023/IMG_2.jpg", b"bbbb"),
+                ("Takeout/Google Photos/Trip/IMG_1(1).jpg", b"aaaa"),
+                ("Takeout/Google Photos/Trip/IMG_2.jpg", b"cccc"),
+            ],
+        );
+        let zip_files = vec![zip_path.to_string_lossy().to_string()];
+
+        let mut media = vec![
+            Media::new("Takeout/Google Photos/Photos from 2023/IMG_1.jpg".into(), 0, 0, "IMG_1.jpg".into(), 4),
+            Media::new("Takeout/Google Photos/Photos from 2023/IMG_2.jpg".into(), 0, 1, "IMG_2.jpg".into(), 4),
+        ];
+        for (entry_index, filename) in [(2, "IMG_1(1).jpg"), (3, "IMG_2.jpg")] {
+            let mut m = Media::new(format!("Takeout/Google Photos/Trip/{}", filename), 0, entry_index, filename.into(), 4);
+            m.albums.push("Trip".into());
+            m.album_only = true;
+            media.push(m);
+        }
+
+        let cb = |_: &crate::ProgressEvent| {};
+        let tp = ThrottledProgress::new(&cb);
+        let mut cache = HashCache::default();
+        let result = deduplicate(media, &zip_files, HashAlgorithm::Sha256, &mut cache, &tp).unwrap();
+
+        // Renamed album copy is folded into the year file; same name and size
+        // with different content stays a separate album-only file.
+        assert_eq!(result.album_merged, 1);
+        assert_eq!(result.media.len(), 3);
+        let img1 = result.media.iter().find(|m| m.filename == "IMG_1.jpg").unwrap();
+        assert_eq!(img1.albums, vec!["Trip".to_string()]);
+        assert!(!img1.album_only);
+        let year_img2 = result.media.iter().find(|m| m.filename == "IMG_2.jpg" && !m.album_only).unwrap();
+        assert!(year_img2.albums.is_empty());
+        assert!(result.media.iter().any(|m| m.filename == "IMG_2.jpg" && m.album_only));
+    }
+
+    #[test]
+    fn test_streaming_hash_algorithms() {
+        let sha = compute_streaming_hash(&b"abc"[..], HashAlgorithm::Sha256).unwrap();
+        assert_eq!(sha, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let b3 = compute_streaming_hash(&b"abc"[..], HashAlgorithm::Blake3).unwrap();
+        assert_eq!(b3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    }
 }
@@ -0,0 +1,196 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checkpoint::{CancellationToken, CancelledError};
+use crate::{Stage, ThrottledProgress};
+
+/// Default free space to keep on the output filesystem (256 MiB)
+pub const DEFAULT_MIN_FREE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// How often to re-check free space while paused for it
+const SPACE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Not enough free space on the output filesystem to write the planned files.
+#[derive(Debug, Clone)]
+pub struct InsufficientSpaceError {
+    /// Output directory (or archive file) that was checked
+    pub path: PathBuf,
+    pub required: u64,
+    pub available: u64,
+    pub min_free: u64,
+}
+
+impl std::fmt::Display for InsufficientSpaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Not enough disk space for {}: {} to write, {} free, {} must stay free",
+            self.path.display(),
+            format_bytes(self.required),
+            format_bytes(self.available),
+            format_bytes(self.min_free),
+        )
+    }
+}
+
+impl InsufficientSpaceError {
+    /// Bytes to free before the write fits.
+    pub fn shortfall(&self) -> u64 {
+        (self.required + self.min_free).saturating_sub(self.available)
+    }
+}
+
+impl std::error::Error for InsufficientSpaceError {}
+
+/// Bytes available to the current user on the filesystem holding `path`.
+/// If `path` does not exist yet, its nearest existing ancestor is queried.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let mut probe = path;
+    while !probe.exists() {
+        match probe.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => probe = parent,
+            _ => {
+                probe = Path::new(".");
+                break;
+            }
+        }
+    }
+    sys_available_space(probe)
+}
+
+#[cfg(unix)]
+fn sys_available_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+fn sys_available_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: wide is NUL-terminated; the other out pointers may be null
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut())
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn sys_available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space query not supported"))
+}
+
+/// Fail early if writing `required` bytes would leave less than `min_free`
+/// bytes free. Filesystems that cannot report free space are not checked.
+pub fn ensure_space(output_dir: &Path, required: u64, min_free: u64) -> Result<(), InsufficientSpaceError> {
+    let Ok(available) = available_space(output_dir) else {
+        return Ok(());
+    };
+    if required.saturating_add(min_free) > available {
+        return Err(InsufficientSpaceError {
+            path: output_dir.to_path_buf(),
+            required,
+            available,
+            min_free,
+        });
+    }
+    Ok(())
+}
+
+/// Block until `needed` bytes can be written while keeping `min_free` bytes
+/// free. While waiting, the token is marked as paused for low space, so other
+/// writer threads pause too, and resumes once space has been freed.
+pub fn wait_for_space(
+    output_dir: &Path,
+    needed: u64,
+    min_free: u64,
+    cancel_token: Option<&CancellationToken>,
+    progress: &ThrottledProgress,
+) -> Result<(), CancelledError> {
+    let has_room = || {
+        available_space(output_dir)
+            .map(|available| available >= needed.saturating_add(min_free))
+            .unwrap_or(true)
+    };
+    if has_room() {
+        return Ok(());
+    }
+
+    if let Some(token) = cancel_token {
+        token.set_space_paused(true);
+    }
+    let message = format!(
+        "Paused: low disk space, waiting for {} free",
+        format_bytes(needed.saturating_add(min_free))
+    );
+    let result = loop {
+        progress.paused(Stage::Write, &message);
+        std::thread::sleep(SPACE_POLL_INTERVAL);
+        if cancel_token.is_some_and(|t| t.is_cancelled()) {
+            break Err(CancelledError);
+        }
+        if has_room() {
+            break Ok(());
+        }
+    };
+    if let Some(token) = cancel_token {
+        token.set_space_paused(false);
+    }
+    result
+}
+
+/// Human-readable byte count (binary units).
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_ensure_space() {
+        let dir = tempdir().unwrap();
+        let available = available_space(&dir.path().join("not/yet/created")).unwrap();
+        assert!(available > 0);
+
+        assert!(ensure_space(dir.path(), 1, 0).is_ok());
+        let err = ensure_space(dir.path(), u64::MAX / 2, DEFAULT_MIN_FREE_BYTES).unwrap_err();
+        assert_eq!(err.min_free, DEFAULT_MIN_FREE_BYTES);
+        assert_eq!(err.path, dir.path());
+        assert!(err.shortfall() > 0);
+        assert!(err.to_string().starts_with("Not enough disk space"));
+
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.5 MiB");
+    }
+}
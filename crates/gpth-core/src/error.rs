@@ -0,0 +1,196 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::checkpoint::CancelledError;
+use crate::disk_space::InsufficientSpaceError;
+
+/// Why processing or verifying stopped. Variants carry the ZIP or file
+/// involved, so frontends can point at it and suggest a remedy.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An input ZIP could not be opened or read
+    #[error("Cannot read {}: {source}", zip.display())]
+    InvalidZip {
+        zip: PathBuf,
+        #[source]
+        source: zip::result::ZipError,
+    },
+    /// Reading or writing a file or directory in the output failed
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// The checkpoint in the output directory cannot be read or resumed
+    #[error("Checkpoint {} cannot be used: {reason}", path.display())]
+    IncompatibleCheckpoint { path: PathBuf, reason: String },
+    /// A file gpth keeps in the output (library, run report) is unreadable
+    #[error("{} is unreadable: {reason}", path.display())]
+    CorruptFile { path: PathBuf, reason: String },
+    /// The output filesystem cannot hold the planned files
+    #[error(transparent)]
+    OutOfSpace(#[from] InsufficientSpaceError),
+    /// Stopped through the cancellation token; the checkpoint was saved
+    #[error("Operation cancelled")]
+    Cancelled,
+    /// A plan was executed with options it was not scanned with
+    #[error("Plan does not match the options: {0}")]
+    InvalidPlan(String),
+    /// An S3 bucket or WebDAV server refused or failed a request
+    #[error("{target}: {message}")]
+    Remote { target: String, message: String },
+    /// `verify` found neither a run report nor a checkpoint
+    #[error("No run report or checkpoint found in {}; nothing to verify", path.display())]
+    NothingToVerify { path: PathBuf },
+    /// Anything else
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Wrap an I/O error on `path`, for use with `map_err`.
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Wrap an error opening or reading the ZIP at `zip`, for use with `map_err`.
+    pub(crate) fn zip<E: Into<zip::result::ZipError>>(zip: &Path) -> impl FnOnce(E) -> Error + '_ {
+        move |source| Error::InvalidZip {
+            zip: zip.to_path_buf(),
+            source: source.into(),
+        }
+    }
+
+    /// Name of the variant in snake_case, for frontends that react to it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::InvalidZip { .. } => "invalid_zip",
+            Error::Io { .. } => "io",
+            Error::IncompatibleCheckpoint { .. } => "incompatible_checkpoint",
+            Error::CorruptFile { .. } => "corrupt_file",
+            Error::OutOfSpace(_) => "out_of_space",
+            Error::Cancelled => "cancelled",
+            Error::InvalidPlan(_) => "invalid_plan",
+            Error::Remote { .. } => "remote",
+            Error::NothingToVerify { .. } => "nothing_to_verify",
+            Error::Other(_) => "other",
+        }
+    }
+
+    /// What the user can do about it, if there is something specific.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            Error::InvalidZip { .. } => {
+                "The ZIP may be incomplete or damaged; download that part of the Takeout again.".to_string()
+            }
+            Error::Io { source, .. } if source.kind() == io::ErrorKind::PermissionDenied => {
+                "Check that the output is writable, or choose another one.".to_string()
+            }
+            Error::IncompatibleCheckpoint { .. } => {
+                "Run again with --force to ignore the checkpoint and start fresh.".to_string()
+            }
+            Error::CorruptFile { path, .. } => {
+                format!("Delete {} to rebuild it, or run again with --force.", path.display())
+            }
+            Error::OutOfSpace(e) => format!(
+                "Free up {} or choose another output directory.",
+                crate::disk_space::format_bytes(e.shortfall())
+            ),
+            Error::Cancelled => "Run again to continue, or use --force to start fresh.".to_string(),
+            Error::NothingToVerify { .. } => "Check that this is the output directory of a finished run.".to_string(),
+            _ => return None,
+        };
+        Some(hint)
+    }
+}
+
+impl From<CancelledError> for Error {
+    fn from(_: CancelledError) -> Self {
+        Error::Cancelled
+    }
+}
+
+/// Internal helpers still return `anyhow::Error`; typed errors they pass
+/// through are recovered, the rest become `Other`.
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        if e.is::<CancelledError>() {
+            return Error::Cancelled;
+        }
+        match e.downcast::<InsufficientSpaceError>() {
+            Ok(space) => Error::OutOfSpace(space),
+            Err(e) => Error::Other(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::CancellationToken;
+    use crate::{ProcessControl, ProcessOptions};
+    use tempfile::tempdir;
+
+    fn options(zip: &Path, output: &Path) -> ProcessOptions {
+        ProcessOptions::for_test(zip, output)
+    }
+
+    #[test]
+    fn test_invalid_zip_names_the_zip() {
+        let dir = tempdir().unwrap();
+        let zip = dir.path().join("takeout-001.zip");
+        std::fs::write(&zip, b"not a zip").unwrap();
+        let err = crate::process(&options(&zip, &dir.path().join("out")), &|_| {}).unwrap_err();
+        match &err {
+            Error::InvalidZip { zip: path, .. } => assert_eq!(path, &zip),
+            other => panic!("expected InvalidZip, got {:?}", other),
+        }
+        assert!(err.to_string().contains("takeout-001.zip"), "{}", err);
+        assert!(err.hint().is_some());
+    }
+
+    #[test]
+    fn test_cancelled_and_corrupt_files() {
+        let dir = tempdir().unwrap();
+        let token = CancellationToken::new();
+        token.cancel();
+        let control = ProcessControl::new().with_cancel_token(token);
+        let err = crate::process_with_control(&options(&dir.path().join("a.zip"), dir.path()), &control, &|_| {})
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled), "{:?}", err);
+        assert_eq!(err.kind(), "cancelled");
+
+        let library = dir.path().join(crate::library::LIBRARY_FILENAME);
+        std::fs::write(&library, b"{").unwrap();
+        match crate::library::Library::load(dir.path()).unwrap_err() {
+            Error::CorruptFile { path, .. } => assert_eq!(path, library),
+            other => panic!("expected CorruptFile, got {:?}", other),
+        }
+
+        let err = crate::verify::verify_output(dir.path(), &crate::ThrottledProgress::new(&|_| {})).unwrap_err();
+        assert!(matches!(err, Error::NothingToVerify { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_typed_errors_survive_anyhow() {
+        let err: Error = anyhow::Error::from(Error::Io {
+            path: PathBuf::from("out/a.jpg"),
+            source: io::Error::from(io::ErrorKind::PermissionDenied),
+        })
+        .into();
+        assert!(matches!(err, Error::Io { .. }));
+        assert!(err.hint().is_some());
+        assert!(matches!(Error::from(anyhow::Error::from(CancelledError)), Error::Cancelled));
+        assert!(matches!(Error::from(anyhow::anyhow!("boom")), Error::Other(_)));
+    }
+}
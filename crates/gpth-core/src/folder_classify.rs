@@ -97,10 +97,8 @@ pub fn extract_album_name(zip_path: &str) -> Option<String> {
         // Use exact matching against known Google Photos folder names
         if GOOGLE_PHOTOS_FOLDERS.contains(&p) {
             let folder_name = parts[i + 1];
-            if !folder_name.is_empty() && !is_year_folder(folder_name) {
-                if i + 2 < parts.len() {
-                    return Some(folder_name.to_string());
-                }
+            if !folder_name.is_empty() && !is_year_folder(folder_name) && i + 2 < parts.len() {
+                return Some(folder_name.to_string());
             }
         }
     }
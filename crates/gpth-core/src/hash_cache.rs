@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::ZipFingerprint;
+use crate::dedup::HashAlgorithm;
+use crate::{Error, Result};
+
+/// Current cache file format version
+const HASH_CACHE_VERSION: u32 = 2;
+
+/// Default cache filename (lives beside the checkpoint)
+pub const HASH_CACHE_FILENAME: &str = ".gpth-cache.json";
+
+/// Cached results for a single ZIP entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CachedEntry {
+    /// Content hash hex, if the entry was hashed during dedup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Whether EXIF was read for this entry
+    #[serde(default)]
+    pub exif_checked: bool,
+    /// EXIF date found (None if the entry had no usable EXIF date)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exif_date: Option<NaiveDateTime>,
+}
+
+/// Cached entries of one ZIP, found again by its fingerprint wherever the
+/// ZIP is and whatever it is called.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ZipCache {
+    fingerprint: ZipFingerprint,
+    /// entry_index -> cached results
+    entries: HashMap<usize, CachedEntry>,
+}
+
+/// Hash and EXIF results persisted across runs in .gpth-cache.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashCache {
+    version: u32,
+    /// Algorithm the cached hashes were computed with
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+    /// Every ZIP seen so far, including ones not given in this run
+    zips: Vec<ZipCache>,
+    /// zip file path in this run -> index into `zips`
+    #[serde(skip)]
+    paths: HashMap<String, usize>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Default for HashCache {
+    fn default() -> Self {
+        Self {
+            version: HASH_CACHE_VERSION,
+            hash_algorithm: HashAlgorithm::default(),
+            zips: Vec::new(),
+            paths: HashMap::new(),
+            dirty: false,
+        }
+    }
+}
+
+impl HashCache {
+    /// Load the cache from the output directory and match this run's ZIPs to
+    /// cached ones by fingerprint, so moved or renamed ZIPs keep their entries.
+    /// ZIPs not given in this run are kept for later runs; a ZIP whose
+    /// contents changed gets a fresh entry. Hashes from a different algorithm
+    /// are dropped. A missing or unreadable cache yields an empty one.
+    pub fn load(output_dir: &Path, zip_files: &[String], algorithm: HashAlgorithm) -> Self {
+        // Unreadable ZIPs are not cached
+        let zips: Vec<(&String, ZipFingerprint)> = zip_files
+            .iter()
+            .filter_map(|zip| ZipFingerprint::of(Path::new(zip)).ok().map(|f| (zip, f)))
+            .collect();
+        Self::load_fingerprinted(output_dir, zips, algorithm)
+    }
+
+    /// `load` for ZIPs the caller has already fingerprinted.
+    pub(crate) fn load_fingerprinted<'a>(
+        output_dir: &Path,
+        zips: impl IntoIterator<Item = (&'a String, ZipFingerprint)>,
+        algorithm: HashAlgorithm,
+    ) -> Self {
+        let path = output_dir.join(HASH_CACHE_FILENAME);
+        let mut cache = File::open(&path)
+            .ok()
+            .and_then(|f| serde_json::from_reader::<_, HashCache>(BufReader::new(f)).ok())
+            .filter(|c| c.version == HASH_CACHE_VERSION)
+            .unwrap_or_default();
+
+        for (zip, fingerprint) in zips {
+            let index = match cache.zips.iter().position(|z| z.fingerprint.matches(&fingerprint)) {
+                Some(index) => index,
+                None => {
+                    cache.zips.push(ZipCache {
+                        fingerprint,
+                        entries: HashMap::new(),
+                    });
+                    cache.dirty = true;
+                    cache.zips.len() - 1
+                }
+            };
+            cache.paths.insert(zip.clone(), index);
+        }
+        if cache.hash_algorithm != algorithm {
+            for zip in cache.zips.iter_mut() {
+                for entry in zip.entries.values_mut() {
+                    entry.hash = None;
+                }
+            }
+            cache.hash_algorithm = algorithm;
+            cache.dirty = true;
+        }
+        cache
+    }
+
+    /// Save the cache to the output directory if anything changed.
+    pub fn save(&mut self, output_dir: &Path) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        fs::create_dir_all(output_dir).map_err(Error::io(output_dir))?;
+        let path = output_dir.join(HASH_CACHE_FILENAME);
+        let temp_path = output_dir.join(".gpth-cache.tmp");
+
+        // Write to temp file first, then rename for atomicity
+        let file = File::create(&temp_path).map_err(Error::io(&temp_path))?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self).map_err(|e| Error::io(&temp_path)(e.into()))?;
+
+        fs::rename(&temp_path, &path).map_err(Error::io(&path))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Get cached results for an entry.
+    pub fn get(&self, zip_file: &str, entry_index: usize) -> Option<&CachedEntry> {
+        self.zips[*self.paths.get(zip_file)?].entries.get(&entry_index)
+    }
+
+    /// Get the cached content hash for an entry.
+    pub fn hash(&self, zip_file: &str, entry_index: usize) -> Option<&str> {
+        self.get(zip_file, entry_index)?.hash.as_deref()
+    }
+
+    /// Get the cached EXIF result for an entry (None if EXIF was never read).
+    pub fn exif_date(&self, zip_file: &str, entry_index: usize) -> Option<Option<NaiveDateTime>> {
+        self.get(zip_file, entry_index)
+            .filter(|e| e.exif_checked)
+            .map(|e| e.exif_date)
+    }
+
+    /// Record the content hash of an entry.
+    pub fn set_hash(&mut self, zip_file: &str, entry_index: usize, hash: &str) {
+        if let Some(entry) = self.entry_mut(zip_file, entry_index) {
+            entry.hash = Some(hash.to_string());
+            self.dirty = true;
+        }
+    }
+
+    /// Record the EXIF result of an entry.
+    pub fn set_exif_date(&mut self, zip_file: &str, entry_index: usize, date: Option<NaiveDateTime>) {
+        if let Some(entry) = self.entry_mut(zip_file, entry_index) {
+            entry.exif_checked = true;
+            entry.exif_date = date;
+            self.dirty = true;
+        }
+    }
+
+    fn entry_mut(&mut self, zip_file: &str, entry_index: usize) -> Option<&mut CachedEntry> {
+        let index = *self.paths.get(zip_file)?;
+        Some(self.zips[index].entries.entry(entry_index).or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_zip(path: &Path, data: &[u8]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        zip.start_file("Takeout/Google Photos/Photos from 2023/a.jpg", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(data).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_cache_roundtrip_and_invalidation() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        write_zip(&zip_path, b"test");
+        let zip_files = vec![zip_path.to_string_lossy().to_string()];
+
+        let mut cache = HashCache::load(dir.path(), &zip_files, HashAlgorithm::Sha256);
+        cache.set_hash(&zip_files[0], 3, "abc");
+        cache.set_exif_date(&zip_files[0], 4, None);
+        cache.save(dir.path()).unwrap();
+
+        let loaded = HashCache::load(dir.path(), &zip_files, HashAlgorithm::Sha256);
+        assert_eq!(loaded.hash(&zip_files[0], 3), Some("abc"));
+        assert_eq!(loaded.exif_date(&zip_files[0], 4), Some(None));
+        assert_eq!(loaded.exif_date(&zip_files[0], 3), None);
+
+        // Switching algorithm keeps EXIF results but not hashes
+        let other = HashCache::load(dir.path(), &zip_files, HashAlgorithm::Blake3);
+        assert_eq!(other.hash(&zip_files[0], 3), None);
+        assert_eq!(other.exif_date(&zip_files[0], 4), Some(None));
+
+        // A ZIP with different contents under the same name starts empty
+        write_zip(&zip_path, b"tesT");
+        let reloaded = HashCache::load(dir.path(), &zip_files, HashAlgorithm::Sha256);
+        assert_eq!(reloaded.hash(&zip_files[0], 3), None);
+    }
+
+    #[test]
+    fn test_cache_follows_moved_zips() {
+        let dir = tempdir().unwrap();
+        let part1 = dir.path().join("takeout-20240101T000000Z-001.zip");
+        let part2 = dir.path().join("takeout-20240101T000000Z-002.zip");
+        write_zip(&part1, b"one");
+        write_zip(&part2, b"two");
+        let both = vec![part1.to_string_lossy().to_string(), part2.to_string_lossy().to_string()];
+
+        let mut cache = HashCache::load(dir.path(), &both, HashAlgorithm::Sha256);
+        cache.set_hash(&both[0], 0, "aaa");
+        cache.set_hash(&both[1], 0, "bbb");
+        cache.save(dir.path()).unwrap();
+
+        // A run over part 2 alone, moved and renamed, still finds its entries
+        fs::create_dir_all(dir.path().join("moved")).unwrap();
+        let moved = dir.path().join("moved/part2.zip");
+        fs::rename(&part2, &moved).unwrap();
+        let subset = vec![moved.to_string_lossy().to_string()];
+        let mut cache = HashCache::load(dir.path(), &subset, HashAlgorithm::Sha256);
+        assert_eq!(cache.hash(&subset[0], 0), Some("bbb"));
+        cache.set_exif_date(&subset[0], 0, None);
+        cache.save(dir.path()).unwrap();
+
+        // ...and part 1's entries survive that run
+        let cache = HashCache::load(dir.path(), &both[..1], HashAlgorithm::Sha256);
+        assert_eq!(cache.hash(&both[0], 0), Some("aaa"));
+    }
+}
@@ -2,19 +2,36 @@ pub mod album_json;
 pub mod checkpoint;
 pub mod date;
 pub mod dedup;
+pub mod disk_space;
+pub mod error;
 pub mod extras;
 pub mod folder_classify;
+pub mod hash_cache;
+pub mod library;
 pub mod media;
+pub mod plan;
+pub mod progress;
+pub mod report;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sanitize;
+pub mod sink;
+pub mod verify;
+#[cfg(feature = "webdav")]
+pub mod webdav;
 pub mod writer;
 pub mod zip_scan;
 
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 
+fn default_min_free_bytes() -> u64 {
+    disk_space::DEFAULT_MIN_FREE_BYTES
+}
+
 fn default_album_dest() -> String {
     "year".to_string()
 }
@@ -36,14 +53,60 @@ pub struct ProcessOptions {
     pub album_json: Option<PathBuf>,
     #[serde(default)]
     pub force: bool,
+    #[serde(default)]
+    pub hash_algorithm: dedup::HashAlgorithm,
+    #[serde(default)]
+    pub durability: writer::Durability,
+    /// Free space to keep on the output filesystem; writing pauses below it
+    #[serde(default = "default_min_free_bytes")]
+    pub min_free_bytes: u64,
+    /// Filesystem whose naming rules output names must follow
+    #[serde(default)]
+    pub name_profile: sanitize::NameProfile,
+    /// Whether names differing only in case collide in the output directory
+    #[serde(default)]
+    pub case_sensitivity: sanitize::CaseSensitivity,
+    /// Unicode normalization form of output file and album folder names
+    #[serde(default)]
+    pub normalization: sanitize::NameNormalization,
+    /// What to do with files already present in the output directory
+    #[serde(default)]
+    pub on_conflict: writer::ConflictPolicy,
+    /// Write a directory tree, a single archive at `output`, or to a bucket or WebDAV server
+    #[serde(default)]
+    pub output_format: sink::OutputFormat,
+    /// S3 endpoint URL (e.g. a local MinIO); defaults to `AWS_ENDPOINT_URL`, then AWS
+    #[serde(default)]
+    pub s3_endpoint: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Progress {
-    pub stage: String,
-    pub current: u64,
-    pub total: u64,
-    pub message: String,
+#[cfg(test)]
+impl ProcessOptions {
+    /// Options for tests: one ZIP into a flat directory, nothing optional on.
+    /// Tests change what they need with struct-update syntax.
+    pub(crate) fn for_test(zip: &Path, output: &Path) -> Self {
+        Self {
+            zip_files: vec![zip.to_string_lossy().to_string()],
+            output: output.to_path_buf(),
+            divide_to_dates: false,
+            skip_extras: false,
+            no_guess: false,
+            albums: false,
+            album_dest: "year".to_string(),
+            album_link: false,
+            album_json: None,
+            force: false,
+            hash_algorithm: dedup::HashAlgorithm::Sha256,
+            durability: writer::Durability::None,
+            min_free_bytes: 0,
+            name_profile: sanitize::NameProfile::Posix,
+            case_sensitivity: sanitize::CaseSensitivity::Sensitive,
+            normalization: sanitize::NameNormalization::Nfc,
+            on_conflict: writer::ConflictPolicy::Rename,
+            output_format: sink::OutputFormat::Dir,
+            s3_endpoint: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,8 +116,24 @@ pub struct ProcessResult {
     pub files_written: u64,
     #[serde(default)]
     pub files_skipped: u64,
+    /// Media an earlier run into the same output had already imported
+    #[serde(default)]
+    pub already_imported: u64,
+    /// Written files moved to new paths after an option change (`migrate`)
+    #[serde(default)]
+    pub files_moved: u64,
     #[serde(default)]
     pub warnings: Vec<String>,
+    /// Algorithm used for the content hashes in this run
+    #[serde(default)]
+    pub hash_algorithm: dedup::HashAlgorithm,
+    /// Files that could not be written (the rest of the run continued)
+    #[serde(default)]
+    pub failed: Vec<writer::FailedItem>,
+    /// Files written before the run was interrupted that had since been
+    /// deleted or changed; they were written again
+    #[serde(default)]
+    pub drifted: Vec<checkpoint::DriftedFile>,
 }
 
 
@@ -65,6 +144,12 @@ pub struct ProcessControl {
     pub resume: bool,
     /// Cancellation token for pause/cancel support.
     pub cancel_token: Option<checkpoint::CancellationToken>,
+    /// On resume after a change to options that decide output paths, move
+    /// the files already written instead of starting fresh.
+    pub migrate: bool,
+    /// On resume, also compare already written files with their ZIP entry's
+    /// CRC-32, not just their size.
+    pub verify_resume: bool,
 }
 
 impl ProcessControl {
@@ -79,6 +164,18 @@ impl ProcessControl {
         self
     }
 
+    /// Create ProcessControl that moves written files when output paths changed.
+    pub fn with_migrate(mut self, migrate: bool) -> Self {
+        self.migrate = migrate;
+        self
+    }
+
+    /// Create ProcessControl that checks the content of already written files on resume.
+    pub fn with_verify_resume(mut self, verify_resume: bool) -> Self {
+        self.verify_resume = verify_resume;
+        self
+    }
+
     /// Create ProcessControl with a cancellation token.
     pub fn with_cancel_token(mut self, token: checkpoint::CancellationToken) -> Self {
         self.cancel_token = Some(token);
@@ -87,87 +184,269 @@ impl ProcessControl {
 }
 
 // Re-export checkpoint types for convenience
-pub use checkpoint::{CancellationToken, CancelledError, Checkpoint, CheckpointSaver, CHECKPOINT_FILENAME};
+pub use checkpoint::{CancellationToken, CancelledError, Checkpoint, CheckpointSaver, DriftedFile, CHECKPOINT_FILENAME, JOURNAL_FILENAME};
+pub use dedup::HashAlgorithm;
+pub use error::{Error, Result};
+pub use plan::Plan;
 
-/// Type alias for progress callback
-pub type ProgressCallback = dyn Fn(&str, u64, u64, &str) + Send + Sync;
-
-/// Throttled progress reporter — emits at most every 200ms or on completion.
-pub struct ThrottledProgress<'a> {
-    inner: &'a ProgressCallback,
-    last_emit: std::sync::Mutex<Instant>,
-}
-
-impl<'a> ThrottledProgress<'a> {
-    pub fn new(inner: &'a ProgressCallback) -> Self {
-        Self {
-            inner,
-            last_emit: std::sync::Mutex::new(Instant::now() - std::time::Duration::from_secs(1)),
-        }
-    }
-
-    pub fn report(&self, stage: &str, current: u64, total: u64, message: &str) {
-        let is_done = current + 1 >= total;
-        if !is_done {
-            let mut last = self.last_emit.lock().unwrap();
-            if last.elapsed().as_millis() < 200 {
-                return;
-            }
-            *last = Instant::now();
-        }
-        (self.inner)(stage, current, total, message);
-    }
-}
+pub use progress::{ProgressCallback, ProgressEvent, SkipReason, Stage, ThrottledProgress};
 
 /// Run the full processing pipeline with progress reporting.
 pub fn process(
     options: &ProcessOptions,
     progress_callback: &ProgressCallback,
-) -> anyhow::Result<ProcessResult> {
+) -> Result<ProcessResult> {
     process_with_control(options, &ProcessControl::default(), progress_callback)
 }
 
 /// Run the full processing pipeline with progress reporting and control options.
+/// Equivalent to [`scan`] followed by [`execute`] on the unmodified plan.
 pub fn process_with_control(
     options: &ProcessOptions,
     control: &ProcessControl,
     progress_callback: &ProgressCallback,
-) -> anyhow::Result<ProcessResult> {
+) -> Result<ProcessResult> {
+    let tp = ThrottledProgress::new(progress_callback);
+    let mut session = prepare(options, control, &tp)?;
+    let plan = scan_inner(options, control, &tp, &mut session)?;
+    execute_inner(options, plan, control, &tp, session)
+}
+
+/// Scan, date and deduplicate the ZIPs without writing anything. The plan
+/// can be inspected and edited (drop media, change dates, rename albums)
+/// before it is passed to [`execute`] with the same options.
+///
+/// For directory output the plan is also saved to the checkpoint, so with
+/// `control.resume` an interrupted scan or write picks up where it stopped.
+pub fn scan(
+    options: &ProcessOptions,
+    control: &ProcessControl,
+    progress_callback: &ProgressCallback,
+) -> Result<Plan> {
     let tp = ThrottledProgress::new(progress_callback);
+    let mut session = prepare(options, control, &tp)?;
+    scan_inner(options, control, &tp, &mut session)
+}
 
+/// Write a plan made by [`scan`] with the same options. With
+/// `control.resume`, files an interrupted run had already written are kept,
+/// as in [`process_with_control`].
+pub fn execute(
+    options: &ProcessOptions,
+    plan: Plan,
+    control: &ProcessControl,
+    progress_callback: &ProgressCallback,
+) -> Result<ProcessResult> {
+    if plan.zip_files != options.zip_files {
+        return Err(Error::InvalidPlan(
+            "it was made from different ZIP files than the options list".to_string(),
+        ));
+    }
+    if plan.hash_algorithm != options.hash_algorithm {
+        return Err(Error::InvalidPlan(format!(
+            "it was hashed with {:?}, but the options use {:?}",
+            plan.hash_algorithm, options.hash_algorithm
+        )));
+    }
+    let tp = ThrottledProgress::new(progress_callback);
+    if let Some(ref token) = control.cancel_token {
+        token.check()?;
+    }
+
+    // With `control.resume`, pick up the checkpoint `scan` left with the
+    // files written so far; record the plan as edited so a resumed run
+    // writes the same thing
+    let mut session = open_session(options, control, &tp)?;
+    if let Some(saver) = session.checkpoint_saver.as_mut() {
+        saver.record_dedup(&plan.media, plan.summary());
+    }
+    execute_inner(options, plan, control, &tp, session)
+}
+
+/// Checkpoint and caches carried from the scan stages to the write stage.
+struct Session {
+    checkpoint_saver: Option<checkpoint::CheckpointSaver>,
+    hash_cache: hash_cache::HashCache,
+}
+
+/// Check the output target and open or create the checkpoint.
+fn prepare(options: &ProcessOptions, control: &ProcessControl, tp: &ThrottledProgress) -> Result<Session> {
     // Check for cancellation early
     if let Some(ref token) = control.cancel_token {
         token.check()?;
     }
 
-    // Load or create checkpoint
-    let mut checkpoint_saver = if control.resume {
-        if let Some(existing) = checkpoint::Checkpoint::load(&options.output)? {
-            if existing.is_compatible(options)? {
-                eprintln!("Resuming from checkpoint: {} files already written", existing.written_files.len());
-                Some(checkpoint::CheckpointSaver::from_existing(existing, options.output.clone()))
-            } else {
-                eprintln!("Checkpoint incompatible with current options, starting fresh");
-                let cp = checkpoint::Checkpoint::new(options)?;
-                Some(checkpoint::CheckpointSaver::new(cp, options.output.clone()))
-            }
-        } else {
-            let cp = checkpoint::Checkpoint::new(options)?;
-            Some(checkpoint::CheckpointSaver::new(cp, options.output.clone()))
-        }
+    // Fail on a bad bucket or server URL, or missing credentials, before the long scan
+    #[cfg(feature = "s3")]
+    if options.output_format == sink::OutputFormat::S3 {
+        s3::S3Target::from_output(&options.output, options.s3_endpoint.as_deref())
+            .map_err(|e| sink_error(options, e))?;
+    }
+    #[cfg(feature = "webdav")]
+    if options.output_format == sink::OutputFormat::Webdav {
+        webdav::WebDavTarget::from_output(&options.output).map_err(|e| sink_error(options, e))?;
+    }
+
+    open_session(options, control, tp)
+}
+
+/// Open or create the checkpoint and load the hash cache, fingerprinting
+/// the input ZIPs once for both. An existing checkpoint is only picked up
+/// with `control.resume`.
+fn open_session(options: &ProcessOptions, control: &ProcessControl, tp: &ThrottledProgress) -> Result<Session> {
+    // Archives are written in one go, and remote targets are resumed from
+    // their listing, so neither keeps a checkpoint or a hash cache
+    if options.output_format != sink::OutputFormat::Dir {
+        return Ok(Session {
+            checkpoint_saver: None,
+            hash_cache: hash_cache::HashCache::default(),
+        });
+    }
+    let fingerprints = checkpoint::get_zip_fingerprints(&options.zip_files)?;
+    let fresh = || {
+        let cp = checkpoint::Checkpoint::with_fingerprints(options, fingerprints.clone());
+        checkpoint::CheckpointSaver::new(cp, options.output.clone())
+    };
+
+    let existing = if control.resume {
+        checkpoint::Checkpoint::load(&options.output)?
     } else {
+        None
+    };
+    let checkpoint_saver = match existing {
+        Some(mut existing) => match existing.compatibility_with(options, &fingerprints) {
+            checkpoint::Compatibility::Compatible { paths_changed, .. } if !paths_changed || control.migrate => {
+                // Also takes over moved or renamed ZIPs
+                existing.adopt_options_with(options, &fingerprints)?;
+                tp.resumed(existing.written_files.len() as u64, existing.migrate_pending);
+                checkpoint::CheckpointSaver::from_existing(existing, options.output.clone())
+            }
+            checkpoint::Compatibility::Compatible { .. } => {
+                tp.warning("Checkpoint was written with options that change output paths, starting fresh (use --migrate to move the files already written)");
+                fresh()
+            }
+            checkpoint::Compatibility::Incompatible => {
+                tp.warning("Checkpoint incompatible with current options, starting fresh");
+                fresh()
+            }
+        },
         // Even without --resume, we create a checkpoint for potential future resume
-        let cp = checkpoint::Checkpoint::new(options)?;
-        Some(checkpoint::CheckpointSaver::new(cp, options.output.clone()))
+        None => fresh(),
     };
 
-    if let Some(ref mut saver) = checkpoint_saver {
-        saver.set_stage("scan");
+    // Hash/EXIF results from earlier runs over the same ZIPs
+    let hash_cache = hash_cache::HashCache::load_fingerprinted(
+        &options.output,
+        options.zip_files.iter().zip(fingerprints),
+        options.hash_algorithm,
+    );
+
+    Ok(Session {
+        checkpoint_saver: Some(checkpoint_saver),
+        hash_cache,
+    })
+}
+
+/// Stages 1 to 3: scan, date and deduplicate, starting from the stage
+/// outputs saved by an interrupted run when there are any.
+fn scan_inner(
+    options: &ProcessOptions,
+    control: &ProcessControl,
+    tp: &ThrottledProgress,
+    session: &mut Session,
+) -> Result<Plan> {
+    let Session {
+        checkpoint_saver,
+        hash_cache,
+    } = session;
+
+    // Stage outputs saved by an interrupted run
+    let resume_point = checkpoint_saver
+        .as_ref()
+        .and_then(|saver| saver.checkpoint().resume_point(options.hash_algorithm));
+
+    let media_list = match resume_point {
+        Some(checkpoint::ResumePoint::Write(media, summary)) => {
+            tp.stage_restored(Stage::Write, media.len() as u64);
+            return Ok(Plan::new(options, media, summary));
+        }
+        Some(checkpoint::ResumePoint::Dedup(media_list)) => {
+            tp.stage_restored(Stage::Dedup, media_list.len() as u64);
+            media_list
+        }
+        None => scan_and_date(options, control, tp, hash_cache, checkpoint_saver.as_mut())?,
+    };
+
+    if media_list.is_empty() {
+        return Ok(Plan::new(options, media_list, checkpoint::DedupSummary::default()));
     }
 
-    // Stage 1: Scan all zips
-    let scan = zip_scan::scan_zips(&options.zip_files, options.skip_extras, options.albums, &tp)?;
-    let mut media_list = scan.media;
+    // Stage 3: Deduplicate (also merges album entries into matching media)
+    let before = media_list.len();
+    let dedup_result = dedup::deduplicate(media_list, &options.zip_files, options.hash_algorithm, hash_cache, tp)?;
+    let mut media_list = dedup_result.media;
+    // Album entries guessed to be copies whose content turned out different
+    let unconfirmed: Vec<usize> = (0..media_list.len())
+        .filter(|&i| media_list[i].album_only && needs_exif(&media_list[i]))
+        .collect();
+    exif_pass(
+        &mut media_list,
+        &unconfirmed,
+        &options.zip_files,
+        !options.no_guess,
+        hash_cache,
+        tp,
+        (Stage::AlbumExif, "Reading EXIF (albums)"),
+    );
+    if options.output_format == sink::OutputFormat::Dir {
+        let _ = hash_cache.save(&options.output);
+    }
+    // Album entries folded into another file are memberships, not duplicates
+    let total_media = (before - dedup_result.album_merged) as u64;
+    let summary = checkpoint::DedupSummary {
+        total_media,
+        duplicates_removed: total_media - media_list.len() as u64,
+        warnings: dedup_result.warnings,
+    };
+
+    if let Some(saver) = checkpoint_saver.as_mut() {
+        saver.record_dedup(&media_list, summary.clone());
+        saver.report_saved(tp);
+    }
+
+    // Check for cancellation
+    if let Some(ref token) = control.cancel_token {
+        if token.check().is_err() {
+            if let Some(saver) = checkpoint_saver.as_mut() {
+                saver.force_save();
+                saver.report_saved(tp);
+            }
+            return Err(Error::Cancelled);
+        }
+    }
+
+    Ok(Plan::new(options, media_list, summary))
+}
+
+/// Stage 4: write the plan's media, albums.json and the run report.
+fn execute_inner(
+    options: &ProcessOptions,
+    plan: Plan,
+    control: &ProcessControl,
+    tp: &ThrottledProgress,
+    session: Session,
+) -> Result<ProcessResult> {
+    let Session {
+        mut checkpoint_saver,
+        hash_cache,
+    } = session;
+    let Plan {
+        media: media_list,
+        total_media,
+        duplicates_removed,
+        warnings,
+        ..
+    } = plan;
 
     if media_list.is_empty() {
         // Clean up checkpoint on success
@@ -175,28 +454,220 @@ pub fn process_with_control(
             let _ = saver.mark_completed();
         }
         return Ok(ProcessResult {
-            total_media: 0,
-            duplicates_removed: 0,
+            total_media,
+            duplicates_removed,
             files_written: 0,
             files_skipped: 0,
-            warnings: vec![],
+            already_imported: 0,
+            files_moved: 0,
+            warnings,
+            hash_algorithm: options.hash_algorithm,
+            failed: vec![],
+            drifted: vec![],
         });
     }
 
+    // Stage 4: Write output
+    let write_options = write_options(options);
+    if options.output_format != sink::OutputFormat::Dir {
+        let write_result = write_to_sink(options, &media_list, &write_options, tp, control.cancel_token.as_ref())?;
+        return Ok(ProcessResult {
+            total_media,
+            duplicates_removed,
+            files_written: write_result.files_written,
+            files_skipped: write_result.files_skipped,
+            already_imported: 0,
+            files_moved: 0,
+            warnings,
+            hash_algorithm: options.hash_algorithm,
+            failed: write_result.failed,
+            drifted: vec![],
+        });
+    }
+    // Files written before an interruption may have been deleted or edited since
+    let drifted = match checkpoint_saver.as_mut() {
+        Some(saver) => saver.validate_written(&media_list, &options.zip_files, control.verify_resume),
+        None => Vec::new(),
+    };
+
+    // Media that an earlier run, possibly over an older overlapping Takeout, already imported
+    let mut library = library::Library::load(&options.output)?
+        .unwrap_or_else(|| library::Library::new(options.hash_algorithm));
+    let mut imported = if options.force {
+        std::collections::HashMap::new()
+    } else {
+        library.find_imported(
+            &media_list,
+            &options.zip_files,
+            &options.output,
+            options.hash_algorithm,
+            &hash_cache,
+            tp,
+        )
+    };
+    // A drifted file's recorded hash is stale; it is written again. Files
+    // the checkpoint tracks are left to it, so a migration can move them.
+    let checkpointed = checkpoint_saver.as_ref().map(|s| s.get_written_map()).unwrap_or_default();
+    imported.retain(|zip_path, path| {
+        !checkpointed.contains_key(zip_path) && !drifted.iter().any(|d| d.output_path == *path)
+    });
+
+    let write_result = writer::write_output(
+        &media_list,
+        &options.zip_files,
+        &options.output,
+        &write_options,
+        &imported,
+        tp,
+        checkpoint_saver.as_mut(),
+        control.cancel_token.as_ref(),
+    )?;
+    let assignments = write_result.assignments;
+    let files_written = write_result.files_written;
+    let already_present: Vec<PathBuf> = media_list
+        .iter()
+        .filter_map(|m| imported.get(&m.zip_path).cloned())
+        .collect();
+    let already_imported = already_present.len() as u64;
+    let files_skipped = write_result.files_skipped.saturating_sub(already_imported);
+    let failed = write_result.failed;
+
+    let failed_zip_paths: Vec<&str> = failed.iter().map(|f| f.zip_path.as_str()).collect();
+    library.record_import(
+        &options.zip_files,
+        &media_list,
+        &assignments,
+        &options.output,
+        options.hash_algorithm,
+        &imported,
+        &failed_zip_paths,
+    );
+    library.save(&options.output)?;
+
+    // Write albums.json if any albums exist
+    let mut album_json_path = None;
+    if options.albums {
+        let has_albums = media_list.iter().any(|m| !m.albums.is_empty());
+        if has_albums {
+            let path = options.album_json.clone()
+                .unwrap_or_else(|| options.output.join("albums.json"));
+            album_json::write_albums_json(
+                &media_list,
+                &assignments,
+                &options.output,
+                &path,
+                options.hash_algorithm,
+            )?;
+            album_json_path = Some(path);
+        }
+    }
+
+    // Record the entry -> output mapping for `verify`
+    let mut run_report = report::RunReport::new(
+        &options.zip_files,
+        options.hash_algorithm,
+        &media_list,
+        &assignments,
+        &write_result.album_files,
+        album_json_path.as_deref(),
+        &options.output,
+    );
+    run_report.renamed = write_result.renamed;
+    run_report.already_present = already_present
+        .iter()
+        .map(|p| p.strip_prefix(&options.output).unwrap_or(p).to_path_buf())
+        .collect();
+    run_report.failed = failed.clone();
+    run_report.save(&options.output)?;
+
+    // Clean up checkpoint on success; keep it when files failed so a re-run retries them
+    if let Some(mut saver) = checkpoint_saver {
+        if failed.is_empty() {
+            let _ = saver.mark_completed();
+        } else {
+            saver.mark_failed(&failed);
+            saver.report_saved(tp);
+        }
+    }
+
+    Ok(ProcessResult {
+        total_media,
+        duplicates_removed,
+        files_written,
+        files_skipped,
+        already_imported,
+        files_moved: write_result.files_moved,
+        warnings,
+        hash_algorithm: options.hash_algorithm,
+        failed,
+        drifted,
+    })
+}
+
+/// Write stage settings derived from the process options.
+pub(crate) fn write_options(options: &ProcessOptions) -> writer::WriteOptions<'_> {
+    let album_dest_opt = if options.albums {
+        Some(options.album_dest.as_str())
+    } else {
+        None
+    };
+    // An archive or bucket may be extracted or synced anywhere, so don't rely on case sensitivity there
+    let case_sensitivity = match options.case_sensitivity {
+        sanitize::CaseSensitivity::Auto if options.output_format != sink::OutputFormat::Dir => {
+            sanitize::CaseSensitivity::Insensitive
+        }
+        case => case,
+    };
+    writer::WriteOptions {
+        divide_to_dates: options.divide_to_dates,
+        album_dest: album_dest_opt,
+        album_link: options.album_link,
+        force: options.force,
+        durability: options.durability,
+        min_free_bytes: options.min_free_bytes,
+        names: sanitize::NameRules::new(options.name_profile, case_sensitivity, &options.output)
+            .with_normalization(options.normalization),
+        on_conflict: options.on_conflict,
+        hash_algorithm: options.hash_algorithm,
+    }
+}
+
+/// Stages 1 and 2: scan the ZIPs, merge album entries, and date everything.
+/// The dated list is saved to the checkpoint so a resumed run can skip both.
+fn scan_and_date(
+    options: &ProcessOptions,
+    control: &ProcessControl,
+    tp: &ThrottledProgress,
+    hash_cache: &mut hash_cache::HashCache,
+    mut checkpoint_saver: Option<&mut checkpoint::CheckpointSaver>,
+) -> Result<Vec<media::Media>> {
+    if let Some(saver) = checkpoint_saver.as_deref_mut() {
+        saver.set_stage("scan");
+    }
+
+    // Stage 1: Scan all zips
+    let scan = zip_scan::scan_zips(&options.zip_files, options.skip_extras, options.albums, tp)?;
+    let mut media_list = scan.media;
+
+    if media_list.is_empty() {
+        return Ok(media_list);
+    }
+
     // Check for cancellation
     if let Some(ref token) = control.cancel_token {
         if token.check().is_err() {
-            if let Some(mut saver) = checkpoint_saver {
+            if let Some(saver) = checkpoint_saver.as_deref_mut() {
                 saver.force_save();
+                saver.report_saved(tp);
             }
-            return Err(checkpoint::CancelledError.into());
+            return Err(Error::Cancelled);
         }
     }
 
     // Use pre-built JSON date map from scan (already has all variants registered)
     let json_dates = scan.json_dates;
 
-    if let Some(ref mut saver) = checkpoint_saver {
+    if let Some(saver) = checkpoint_saver.as_deref_mut() {
         saver.set_stage("date");
     }
 
@@ -205,6 +676,7 @@ pub fn process_with_control(
     let total = media_list.len() as u64;
 
     // JSON + guess pass (fast, single report)
+    tp.start(Stage::Date, total);
     for m in media_list.iter_mut() {
         let json_date = date::json::find_json_date(&m.zip_path, &json_dates);
 
@@ -213,140 +685,70 @@ pub fn process_with_control(
             m.date_accuracy = result.accuracy;
         }
     }
-    tp.report("date", total, total, "JSON/filename dates extracted");
+    tp.report(Stage::Date, total, total, "JSON/filename dates extracted");
+    tp.finish(Stage::Date);
 
     // EXIF pass
-    let exif_targets: Vec<usize> = media_list
-        .iter()
-        .enumerate()
-        .filter(|(_, m)| {
-            m.date.is_none()
-                && m.size <= 32 * 1024 * 1024
-                && mime_guess::from_path(&m.filename)
-                    .first()
-                    .map_or(false, |mime| mime.type_() == mime_guess::mime::IMAGE)
-        })
-        .map(|(i, _)| i)
+    let exif_targets: Vec<usize> = (0..media_list.len())
+        .filter(|&i| needs_exif(&media_list[i]))
         .collect();
-
-    if !exif_targets.is_empty() {
-        let exif_total = exif_targets.len() as u64;
-        let mut by_zip: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
-        for &idx in &exif_targets {
-            by_zip.entry(media_list[idx].zip_index).or_default().push(idx);
-        }
-
-        let num_threads = rayon::current_num_threads();
-        let mut all_results: Vec<(usize, Option<date::DateResult>)> = Vec::new();
-        let counter = AtomicU64::new(0);
-
-        for (zip_idx, indices) in &by_zip {
-            let chunk_size = (indices.len() + num_threads - 1) / num_threads;
-            let chunks: Vec<&[usize]> = indices.chunks(chunk_size).collect();
-            let zip_path = &options.zip_files[*zip_idx];
-
-            let chunk_results: Vec<Vec<(usize, Option<date::DateResult>)>> =
-                std::thread::scope(|s| {
-                    let handles: Vec<_> = chunks
-                        .into_iter()
-                        .map(|chunk| {
-                            let media = &media_list;
-                            let zip_path = zip_path;
-                            let counter = &counter;
-                            let tp = &tp;
-                            s.spawn(move || -> Vec<(usize, Option<date::DateResult>)> {
-                                let Ok(file) = std::fs::File::open(zip_path) else {
-                                    return vec![];
-                                };
-                                let Ok(mut archive) = zip::ZipArchive::new(file) else {
-                                    return vec![];
-                                };
-                                let mut results = Vec::with_capacity(chunk.len());
-                                for &midx in chunk {
-                                    let m = &media[midx];
-                                    let result = archive
-                                        .by_index(m.entry_index)
-                                        .ok()
-                                        .and_then(|mut entry| {
-                                            let mut bytes = Vec::with_capacity(entry.size() as usize);
-                                            entry.read_to_end(&mut bytes).ok()?;
-                                            Some(bytes)
-                                        })
-                                        .and_then(|bytes| {
-                                            date::extract_date(None, Some(&bytes), &m.filename, allow_guess)
-                                        });
-                                    let current = counter.fetch_add(1, Ordering::Relaxed);
-                                    tp.report("date-exif", current, exif_total, "Reading EXIF");
-                                    results.push((midx, result));
-                                }
-                                results
-                            })
-                        })
-                        .collect();
-                    handles.into_iter().map(|h| h.join().unwrap()).collect()
-                });
-
-            for chunk in chunk_results {
-                all_results.extend(chunk);
-            }
-        }
-
-        for (idx, result) in all_results {
-            if let Some(r) = result {
-                media_list[idx].date = Some(r.date);
-                media_list[idx].date_accuracy = r.accuracy;
-            }
-        }
-    }
+    exif_pass(
+        &mut media_list,
+        &exif_targets,
+        &options.zip_files,
+        allow_guess,
+        hash_cache,
+        tp,
+        (Stage::Exif, "Reading EXIF"),
+    );
 
     // Check for cancellation
     if let Some(ref token) = control.cancel_token {
         if token.check().is_err() {
-            if let Some(mut saver) = checkpoint_saver {
+            if let Some(saver) = checkpoint_saver.as_deref_mut() {
                 saver.force_save();
+                saver.report_saved(tp);
             }
-            return Err(checkpoint::CancelledError.into());
+            return Err(Error::Cancelled);
         }
     }
 
-    // Stage 2.5: Album merge (O(N+M) using HashMap)
+    // Stage 2.5: Album merge
+    // Every album entry becomes a candidate Media; dedup folds the ones whose
+    // content matches a year-folder file (or another album entry) into it.
+    // (filename, size) is only a fast first guess that puts off reading EXIF.
     let album_only_start = media_list.len();
+    let mut album_guesses: Vec<bool> = Vec::new();
     if options.albums && !scan.album_entries.is_empty() {
-        // Build lookup index: (filename, size) -> media index
-        let mut media_index: std::collections::HashMap<(String, u64), usize> =
+        // Build lookup index: (filename, size) -> year-folder media index
+        let mut media_index: std::collections::HashMap<(&str, u64), usize> =
             std::collections::HashMap::with_capacity(media_list.len());
         for (i, m) in media_list.iter().enumerate() {
-            media_index.insert((m.filename.clone(), m.size), i);
+            media_index.insert((m.filename.as_str(), m.size), i);
         }
 
+        let mut album_media = Vec::new();
         for (album_name, entries) in &scan.album_entries {
             for ae in entries {
-                // O(1) lookup using HashMap
-                if let Some(&idx) = media_index.get(&(ae.filename.clone(), ae.size)) {
-                    if !media_list[idx].albums.contains(album_name) {
-                        media_list[idx].albums.push(album_name.clone());
-                    }
-                } else {
-                    // Album-only file: add as new Media
-                    let mut m = media::Media::new(
-                        ae.zip_path.clone(),
-                        ae.zip_index,
-                        ae.entry_index,
-                        ae.filename.clone(),
-                        ae.size,
-                    );
-                    m.albums.push(album_name.clone());
-                    // Add to index for subsequent lookups within same album scan
-                    media_index.insert((ae.filename.clone(), ae.size), media_list.len());
-                    media_list.push(m);
-                }
+                let mut m = media::Media::new(
+                    ae.zip_path.clone(),
+                    ae.zip_index,
+                    ae.entry_index,
+                    ae.filename.clone(),
+                    ae.size,
+                );
+                m.albums.push(album_name.clone());
+                m.album_only = true;
+                album_guesses.push(media_index.contains_key(&(ae.filename.as_str(), ae.size)));
+                album_media.push(m);
             }
         }
+        media_list.extend(album_media);
     }
 
-    // Stage 2.6: Extract dates for album-only files
+    // Stage 2.6: Extract dates for album entries
     if album_only_start < media_list.len() {
-        // JSON + guess pass for album-only files
+        // JSON + guess pass for album entries
         for m in media_list[album_only_start..].iter_mut() {
             let json_date = date::json::find_json_date(&m.zip_path, &json_dates);
             if let Some(result) = date::extract_date(json_date, None, &m.filename, allow_guess) {
@@ -355,182 +757,253 @@ pub fn process_with_control(
             }
         }
 
-        // EXIF pass for album-only files
-        let album_exif_targets: Vec<usize> = media_list[album_only_start..]
-            .iter()
-            .enumerate()
-            .filter(|(_, m)| {
-                m.date.is_none()
-                    && m.size <= 32 * 1024 * 1024
-                    && mime_guess::from_path(&m.filename)
-                        .first()
-                        .map_or(false, |mime| mime.type_() == mime_guess::mime::IMAGE)
-            })
-            .map(|(i, _)| album_only_start + i)
+        // EXIF pass for album entries. Likely copies of a year-folder file
+        // share its size, so dedup hashes them and folds the confirmed ones
+        // into it; the rest read EXIF after dedup.
+        let album_exif_targets: Vec<usize> = (album_only_start..media_list.len())
+            .filter(|&i| !album_guesses[i - album_only_start] && needs_exif(&media_list[i]))
             .collect();
+        exif_pass(
+            &mut media_list,
+            &album_exif_targets,
+            &options.zip_files,
+            allow_guess,
+            hash_cache,
+            tp,
+            (Stage::AlbumExif, "Reading EXIF (albums)"),
+        );
+    }
+    if options.output_format == sink::OutputFormat::Dir {
+        let _ = hash_cache.save(&options.output);
+    }
 
-        if !album_exif_targets.is_empty() {
-            let exif_total = album_exif_targets.len() as u64;
-            let mut by_zip: std::collections::HashMap<usize, Vec<usize>> =
-                std::collections::HashMap::new();
-            for &idx in &album_exif_targets {
-                by_zip
-                    .entry(media_list[idx].zip_index)
-                    .or_default()
-                    .push(idx);
-            }
-
-            let num_threads = rayon::current_num_threads();
-            let mut all_results: Vec<(usize, Option<date::DateResult>)> = Vec::new();
-            let counter = AtomicU64::new(0);
-
-            for (zip_idx, indices) in &by_zip {
-                let chunk_size = (indices.len() + num_threads - 1) / num_threads;
-                let chunks: Vec<&[usize]> = indices.chunks(chunk_size).collect();
-                let zip_path = &options.zip_files[*zip_idx];
-
-                let chunk_results: Vec<Vec<(usize, Option<date::DateResult>)>> =
-                    std::thread::scope(|s| {
-                        let handles: Vec<_> = chunks
-                            .into_iter()
-                            .map(|chunk| {
-                                let media = &media_list;
-                                let zip_path = zip_path;
-                                let counter = &counter;
-                                let tp = &tp;
-                                s.spawn(move || -> Vec<(usize, Option<date::DateResult>)> {
-                                    let Ok(file) = std::fs::File::open(zip_path) else {
-                                        return vec![];
-                                    };
-                                    let Ok(mut archive) = zip::ZipArchive::new(file) else {
-                                        return vec![];
-                                    };
-                                    let mut results = Vec::with_capacity(chunk.len());
-                                    for &midx in chunk {
-                                        let m = &media[midx];
-                                        let result = archive
-                                            .by_index(m.entry_index)
-                                            .ok()
-                                            .and_then(|mut entry| {
-                                                let mut bytes =
-                                                    Vec::with_capacity(entry.size() as usize);
-                                                entry.read_to_end(&mut bytes).ok()?;
-                                                Some(bytes)
-                                            })
-                                            .and_then(|bytes| {
-                                                date::extract_date(
-                                                    None,
-                                                    Some(&bytes),
-                                                    &m.filename,
-                                                    allow_guess,
-                                                )
-                                            });
-                                        let current = counter.fetch_add(1, Ordering::Relaxed);
-                                        tp.report(
-                                            "date-exif-album",
-                                            current,
-                                            exif_total,
-                                            "Reading EXIF (albums)",
-                                        );
-                                        results.push((midx, result));
-                                    }
-                                    results
-                                })
-                            })
-                            .collect();
-                        handles.into_iter().map(|h| h.join().unwrap()).collect()
-                    });
+    if let Some(saver) = checkpoint_saver {
+        saver.record_dates(&media_list);
+        saver.report_saved(tp);
+    }
+    Ok(media_list)
+}
 
-                for chunk in chunk_results {
-                    all_results.extend(chunk);
-                }
-            }
+/// Write stage for archive and remote output: media, albums.json and the run
+/// report all go to the sink for `options.output` (albums.json goes to
+/// `options.album_json` instead when set).
+fn write_to_sink(
+    options: &ProcessOptions,
+    media_list: &[media::Media],
+    write_options: &writer::WriteOptions,
+    tp: &ThrottledProgress,
+    cancel_token: Option<&checkpoint::CancellationToken>,
+) -> Result<writer::WriteResult> {
+    add_to_sink(options, media_list, write_options, tp, cancel_token).map_err(|e| sink_error(options, e))
+}
 
-            for (idx, result) in all_results {
-                if let Some(r) = result {
-                    media_list[idx].date = Some(r.date);
-                    media_list[idx].date_accuracy = r.accuracy;
-                }
-            }
-        }
+/// Classify an error from an archive, bucket or server by the output it
+/// concerns. Typed errors passed up through the sink are kept as they are.
+fn sink_error(options: &ProcessOptions, e: anyhow::Error) -> Error {
+    let e = match Error::from(e) {
+        Error::Other(e) => e,
+        e => return e,
+    };
+    if matches!(options.output_format, sink::OutputFormat::Zip | sink::OutputFormat::Tar) {
+        return match e.downcast::<std::io::Error>() {
+            Ok(source) => Error::Io {
+                path: options.output.clone(),
+                source,
+            },
+            Err(e) => Error::Other(e),
+        };
+    }
+    Error::Remote {
+        target: options.output.to_string_lossy().to_string(),
+        message: format!("{:#}", e),
     }
+}
 
-    // Check for cancellation
-    if let Some(ref token) = control.cancel_token {
-        if token.check().is_err() {
-            if let Some(mut saver) = checkpoint_saver {
-                saver.force_save();
-            }
-            return Err(checkpoint::CancelledError.into());
+/// Body of `write_to_sink`, with the sink's own errors unclassified.
+fn add_to_sink(
+    options: &ProcessOptions,
+    media_list: &[media::Media],
+    write_options: &writer::WriteOptions,
+    tp: &ThrottledProgress,
+    cancel_token: Option<&checkpoint::CancellationToken>,
+) -> anyhow::Result<writer::WriteResult> {
+    let (mut target, space_dir): (Box<dyn sink::OutputSink>, Option<PathBuf>) = match options.output_format {
+        #[cfg(feature = "s3")]
+        sink::OutputFormat::S3 => {
+            let s3_target = s3::S3Target::from_output(&options.output, options.s3_endpoint.as_deref())?;
+            (Box::new(s3::S3Sink::new(s3_target)), None)
         }
+        #[cfg(feature = "webdav")]
+        sink::OutputFormat::Webdav => {
+            let webdav_target = webdav::WebDavTarget::from_output(&options.output)?;
+            (Box::new(webdav::WebDavSink::new(webdav_target)), None)
+        }
+        format => {
+            let archive = sink::ArchiveWriter::create(&options.output, format)?;
+            let space_dir = archive.space_dir().to_path_buf();
+            (Box::new(archive), Some(space_dir))
+        }
+    };
+    let mut write_result = sink::write_media(
+        target.as_mut(),
+        media_list,
+        &options.zip_files,
+        write_options,
+        space_dir.as_deref(),
+        tp,
+        cancel_token,
+    )?;
+    let archive_root = Path::new("");
+
+    let mut album_json_path = None;
+    if options.albums && media_list.iter().any(|m| !m.albums.is_empty()) {
+        let json = album_json::albums_json_bytes(
+            media_list,
+            &write_result.assignments,
+            archive_root,
+            options.hash_algorithm,
+        )?;
+        let path = match &options.album_json {
+            Some(path) => {
+                std::fs::write(path, &json).map_err(Error::io(path))?;
+                path.clone()
+            }
+            None => {
+                sink::add_bytes(target.as_mut(), "albums.json", &json)?;
+                PathBuf::from("albums.json")
+            }
+        };
+        album_json_path = Some(path);
     }
 
-    if let Some(ref mut saver) = checkpoint_saver {
-        saver.set_stage("dedup");
-    }
+    let mut run_report = report::RunReport::new(
+        &options.zip_files,
+        options.hash_algorithm,
+        media_list,
+        &write_result.assignments,
+        &write_result.album_files,
+        album_json_path.as_deref(),
+        archive_root,
+    );
+    run_report.renamed = std::mem::take(&mut write_result.renamed);
+    run_report.failed = write_result.failed.clone();
+    sink::add_bytes(target.as_mut(), report::REPORT_FILENAME, &serde_json::to_vec_pretty(&run_report)?)?;
+
+    target.finish()?;
+    Ok(write_result)
+}
 
-    // Stage 3: Deduplicate
-    let before = media_list.len();
-    let dedup_result = dedup::deduplicate(media_list, &options.zip_files, &tp)?;
-    media_list = dedup_result.media;
-    let warnings = dedup_result.warnings;
-    let duplicates_removed = (before - media_list.len()) as u64;
+/// Whether a media file still needs its date read from EXIF.
+fn needs_exif(m: &media::Media) -> bool {
+    m.date.is_none()
+        && m.size <= 32 * 1024 * 1024
+        && mime_guess::from_path(&m.filename)
+            .first()
+            .is_some_and(|mime| mime.type_() == mime_guess::mime::IMAGE)
+}
 
-    // Check for cancellation
-    if let Some(ref token) = control.cancel_token {
-        if token.check().is_err() {
-            if let Some(mut saver) = checkpoint_saver {
-                saver.force_save();
-            }
-            return Err(checkpoint::CancelledError.into());
+/// Read EXIF dates for the given media indices, using and filling the hash cache.
+fn exif_pass(
+    media_list: &mut [media::Media],
+    targets: &[usize],
+    zip_files: &[String],
+    allow_guess: bool,
+    cache: &mut hash_cache::HashCache,
+    tp: &ThrottledProgress,
+    (stage, message): (Stage, &str),
+) {
+    // Entries whose EXIF was read in an earlier run skip the ZIP entirely
+    let mut results: Vec<(usize, Option<chrono::NaiveDateTime>)> = Vec::new();
+    let mut pending: Vec<usize> = Vec::new();
+    for &idx in targets {
+        let m = &media_list[idx];
+        match cache.exif_date(&zip_files[m.zip_index], m.entry_index) {
+            Some(cached) => results.push((idx, cached)),
+            None => pending.push(idx),
         }
     }
 
-    if let Some(ref mut saver) = checkpoint_saver {
-        saver.set_stage("write");
-    }
+    if !pending.is_empty() {
+        let exif_total = pending.len() as u64;
+        let exif_bytes: u64 = pending.iter().map(|&idx| media_list[idx].size).sum();
+        tp.start(stage, exif_total);
+        let mut by_zip: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
+        for &idx in &pending {
+            by_zip.entry(media_list[idx].zip_index).or_default().push(idx);
+        }
 
-    // Stage 4: Write output
-    let album_dest_opt = if options.albums {
-        Some(options.album_dest.as_str())
-    } else {
-        None
-    };
-    let write_result = writer::write_output(
-        &media_list,
-        &options.zip_files,
-        &options.output,
-        options.divide_to_dates,
-        album_dest_opt,
-        options.album_link,
-        options.force,
-        &tp,
-        checkpoint_saver.as_mut(),
-        control.cancel_token.as_ref(),
-    )?;
-    let assignments = write_result.assignments;
-    let files_skipped = write_result.files_skipped;
+        let num_threads = rayon::current_num_threads();
+        let counter = AtomicU64::new(0);
+        let bytes_counter = AtomicU64::new(0);
 
-    // Write albums.json if any albums exist
-    if options.albums {
-        let has_albums = media_list.iter().any(|m| !m.albums.is_empty());
-        if has_albums {
-            let album_json_path = options.album_json.clone()
-                .unwrap_or_else(|| options.output.join("albums.json"));
-            album_json::write_albums_json(&media_list, &assignments, &options.output, &album_json_path)?;
+        for (zip_idx, indices) in &by_zip {
+            let chunk_size = indices.len().div_ceil(num_threads);
+            let chunks: Vec<&[usize]> = indices.chunks(chunk_size).collect();
+            let zip_path = &zip_files[*zip_idx];
+
+            // Inner None = entry could not be read (not cached)
+            let chunk_results: Vec<Vec<(usize, Option<Option<chrono::NaiveDateTime>>)>> =
+                std::thread::scope(|s| {
+                    let handles: Vec<_> = chunks
+                        .into_iter()
+                        .map(|chunk| {
+                            let media = &*media_list;
+                            let counter = &counter;
+                            let bytes_counter = &bytes_counter;
+                            s.spawn(move || -> Vec<(usize, Option<Option<chrono::NaiveDateTime>>)> {
+                                let Ok(file) = std::fs::File::open(zip_path) else {
+                                    return vec![];
+                                };
+                                let Ok(mut archive) = zip::ZipArchive::new(file) else {
+                                    return vec![];
+                                };
+                                let mut results = Vec::with_capacity(chunk.len());
+                                for &midx in chunk {
+                                    let m = &media[midx];
+                                    let result = archive
+                                        .by_index(m.entry_index)
+                                        .ok()
+                                        .and_then(|mut entry| {
+                                            let mut bytes = Vec::with_capacity(entry.size() as usize);
+                                            entry.read_to_end(&mut bytes).ok()?;
+                                            Some(bytes)
+                                        })
+                                        .map(|bytes| date::exif::extract_exif_date(&bytes));
+                                    let current = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                                    let bytes = bytes_counter.fetch_add(m.size, Ordering::Relaxed) + m.size;
+                                    tp.report_bytes(stage, current, exif_total, (bytes, exif_bytes), message);
+                                    results.push((midx, result));
+                                }
+                                results
+                            })
+                        })
+                        .collect();
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                });
+
+            for (idx, result) in chunk_results.into_iter().flatten() {
+                let Some(exif_date) = result else {
+                    results.push((idx, None));
+                    continue;
+                };
+                let m = &media_list[idx];
+                cache.set_exif_date(zip_path, m.entry_index, exif_date);
+                results.push((idx, exif_date));
+            }
         }
+        tp.finish(stage);
     }
 
-    // Clean up checkpoint on success
-    if let Some(mut saver) = checkpoint_saver {
-        let _ = saver.mark_completed();
+    for (idx, exif_date) in results {
+        let m = &mut media_list[idx];
+        // Same priority as date::extract_date: EXIF first, then filename guess
+        let result = match exif_date {
+            Some(date) => Some(date::DateResult { date, accuracy: 1 }),
+            None => date::extract_date(None, None, &m.filename, allow_guess),
+        };
+        if let Some(r) = result {
+            m.date = Some(r.date);
+            m.date_accuracy = r.accuracy;
+        }
     }
-
-    Ok(ProcessResult {
-        total_media: before as u64,
-        duplicates_removed,
-        files_written: media_list.len() as u64 - files_skipped,
-        files_skipped,
-        warnings,
-    })
 }
@@ -0,0 +1,344 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::dedup::{compute_streaming_hash, HashAlgorithm};
+use crate::hash_cache::HashCache;
+use crate::media::Media;
+use crate::{Error, Result, Stage, ThrottledProgress};
+
+/// Current library file format version
+const LIBRARY_VERSION: u32 = 1;
+
+/// Default library filename
+pub const LIBRARY_FILENAME: &str = ".gpth-library.json";
+
+/// A media file imported into the output directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryFile {
+    /// Output path relative to the output directory
+    pub output_path: PathBuf,
+    pub size: u64,
+    /// Content hash; filled in the first time another file of the same size
+    /// has to be compared with it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// File name of the ZIP it was imported from
+    pub zip_file: String,
+    /// Relative path inside that ZIP
+    pub zip_path: String,
+    /// Index into `Library::imports`
+    pub import: usize,
+}
+
+/// One run that added media to the output directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Import {
+    pub timestamp: DateTime<Utc>,
+    pub zip_files: Vec<String>,
+    /// Media written by this run
+    pub added: u64,
+    /// Media skipped because an earlier run had imported them
+    pub already_present: u64,
+}
+
+/// Everything imported into an output directory, stored as .gpth-library.json.
+/// Unlike the checkpoint it outlives completed runs, so a newer Takeout that
+/// overlaps an earlier one only adds what is new.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Library {
+    pub version: u32,
+    /// Algorithm the recorded hashes were computed with
+    pub hash_algorithm: HashAlgorithm,
+    pub imports: Vec<Import>,
+    pub files: Vec<LibraryFile>,
+}
+
+impl Library {
+    /// Create an empty library.
+    pub fn new(hash_algorithm: HashAlgorithm) -> Self {
+        Self {
+            version: LIBRARY_VERSION,
+            hash_algorithm,
+            imports: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Load the library from an output directory.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = output_dir.join(LIBRARY_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&path).map_err(Error::io(&path))?;
+        let reader = BufReader::new(file);
+        let library: Library = serde_json::from_reader(reader).map_err(|e| Error::CorruptFile {
+            path: path.clone(),
+            reason: e.to_string(),
+        })?;
+        if library.version != LIBRARY_VERSION {
+            return Err(Error::CorruptFile {
+                path,
+                reason: format!("unsupported version {}", library.version),
+            });
+        }
+
+        Ok(Some(library))
+    }
+
+    /// Save the library to the output directory.
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = output_dir.join(LIBRARY_FILENAME);
+        let temp_path = output_dir.join(".gpth-library.tmp");
+
+        // Write to temp file first, then rename for atomicity
+        let file = File::create(&temp_path).map_err(Error::io(&temp_path))?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer(writer, self).map_err(|e| Error::io(&temp_path)(e.into()))?;
+
+        fs::rename(&temp_path, &path).map_err(Error::io(&path))?;
+        Ok(())
+    }
+
+    /// Find media an earlier run already imported: same size and content as
+    /// a library file that is still in `output_dir`. Returns zip path ->
+    /// output path of the earlier copy.
+    ///
+    /// Only sizes that occur in the library are hashed, on both sides. ZIP
+    /// entries reuse dedup and cached hashes when `algorithm` matches the
+    /// library's; hashes of output files are recorded in the library.
+    pub fn find_imported(
+        &mut self,
+        media: &[Media],
+        zip_files: &[String],
+        output_dir: &Path,
+        algorithm: HashAlgorithm,
+        cache: &HashCache,
+        progress: &ThrottledProgress,
+    ) -> HashMap<String, PathBuf> {
+        let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, f) in self.files.iter().enumerate() {
+            by_size.entry(f.size).or_default().push(i);
+        }
+        let candidates: Vec<&Media> = media.iter().filter(|m| by_size.contains_key(&m.size)).collect();
+
+        if candidates.is_empty() {
+            return HashMap::new();
+        }
+
+        let total = candidates.len() as u64;
+        progress.start(Stage::Library, total);
+        let mut archives: HashMap<usize, Option<ZipArchive<File>>> = HashMap::new();
+        let mut imported = HashMap::new();
+        for (current, m) in candidates.into_iter().enumerate() {
+            progress.report(Stage::Library, current as u64, total, "Matching earlier imports");
+
+            let known = if algorithm == self.hash_algorithm {
+                m.hash
+                    .clone()
+                    .or_else(|| cache.hash(&zip_files[m.zip_index], m.entry_index).map(str::to_string))
+            } else {
+                None
+            };
+            let hash = match known {
+                Some(hash) => hash,
+                None => {
+                    let archive = archives
+                        .entry(m.zip_index)
+                        .or_insert_with(|| File::open(&zip_files[m.zip_index]).ok().and_then(|f| ZipArchive::new(f).ok()));
+                    let Some(archive) = archive else {
+                        continue;
+                    };
+                    let Ok(entry) = archive.by_index(m.entry_index) else {
+                        continue;
+                    };
+                    match compute_streaming_hash(entry, self.hash_algorithm) {
+                        Ok(hash) => hash,
+                        Err(_) => continue,
+                    }
+                }
+            };
+
+            for &i in &by_size[&m.size] {
+                let f = &mut self.files[i];
+                let path = output_dir.join(&f.output_path);
+                // Deleted or changed since it was imported
+                if !fs::metadata(&path).is_ok_and(|meta| meta.len() == f.size) {
+                    continue;
+                }
+                if f.hash.is_none() {
+                    f.hash = File::open(&path)
+                        .and_then(|file| compute_streaming_hash(file, self.hash_algorithm))
+                        .ok();
+                }
+                if f.hash.as_deref() == Some(hash.as_str()) {
+                    imported.insert(m.zip_path.clone(), path);
+                    break;
+                }
+            }
+        }
+        progress.report(Stage::Library, total, total, "Matched earlier imports");
+        progress.finish(Stage::Library);
+        imported
+    }
+
+    /// Record a run: every media written to `assignments` except those in
+    /// `imported` (already in the library) and `failed_zip_paths`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_import(
+        &mut self,
+        zip_files: &[String],
+        media: &[Media],
+        assignments: &[PathBuf],
+        output_dir: &Path,
+        algorithm: HashAlgorithm,
+        imported: &HashMap<String, PathBuf>,
+        failed_zip_paths: &[&str],
+    ) {
+        let import = self.imports.len();
+        let mut added: Vec<LibraryFile> = Vec::new();
+        for (m, dest) in media.iter().zip(assignments) {
+            if imported.contains_key(&m.zip_path) || failed_zip_paths.contains(&m.zip_path.as_str()) {
+                continue;
+            }
+            added.push(LibraryFile {
+                output_path: dest.strip_prefix(output_dir).unwrap_or(dest).to_path_buf(),
+                size: m.size,
+                hash: m.hash.clone().filter(|_| algorithm == self.hash_algorithm),
+                zip_file: Path::new(&zip_files[m.zip_index])
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                zip_path: m.zip_path.clone(),
+                import,
+            });
+        }
+        // Files rewritten in place, or moved, by a resumed or repeated run replace their old record
+        let paths: HashSet<&Path> = added.iter().map(|f| f.output_path.as_path()).collect();
+        let sources: HashSet<(&str, &str)> = added.iter().map(|f| (f.zip_file.as_str(), f.zip_path.as_str())).collect();
+        self.files.retain(|f| {
+            !paths.contains(f.output_path.as_path()) && !sources.contains(&(f.zip_file.as_str(), f.zip_path.as_str()))
+        });
+        let added_count = added.len() as u64;
+        self.files.extend(added);
+        self.imports.push(Import {
+            timestamp: Utc::now(),
+            zip_files: zip_files.to_vec(),
+            added: added_count,
+            already_present: imported.len() as u64,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in files {
+            zip.start_file(format!("Takeout/Google Photos/Photos from 2023/{}", name), SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_incremental_import() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out");
+        let first = dir.path().join("takeout-1.zip");
+        write_zip(&first, &[("a.jpg", b"aaaa"), ("b.jpg", b"bbbbbb")]);
+        let mut options = crate::ProcessOptions::for_test(&first, &output);
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!(result.files_written, 2);
+
+        // A newer Takeout: b.jpg again under another name, plus a new file
+        // and one that only shares a size with a.jpg
+        let second = dir.path().join("takeout-2.zip");
+        write_zip(&second, &[("b-copy.jpg", b"bbbbbb"), ("c.jpg", b"cc"), ("d.jpg", b"dddd")]);
+        options.zip_files = vec![second.to_string_lossy().to_string()];
+        let skipped = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events = skipped.clone();
+        let result = crate::process(&options, &move |event| {
+            if let crate::ProgressEvent::FileSkipped { zip_path, reason, .. } = event {
+                events.lock().unwrap().push((zip_path.clone(), *reason));
+            }
+        })
+        .unwrap();
+        assert_eq!(result.files_written, 2);
+        assert_eq!(result.already_imported, 1);
+        assert_eq!(
+            *skipped.lock().unwrap(),
+            [(
+                "Takeout/Google Photos/Photos from 2023/b-copy.jpg".to_string(),
+                crate::SkipReason::AlreadyImported
+            )]
+        );
+        assert!(!output.join("b-copy.jpg").exists());
+        assert_eq!(fs::read(output.join("d.jpg")).unwrap(), b"dddd");
+
+        let library = Library::load(&output).unwrap().unwrap();
+        assert_eq!(library.imports.len(), 2);
+        assert_eq!(library.imports[1].already_present, 1);
+        assert_eq!(library.files.len(), 4);
+        let a = library.files.iter().find(|f| f.output_path == Path::new("a.jpg")).unwrap();
+        assert_eq!(a.zip_file, "takeout-1.zip");
+        // Hashed when d.jpg had to be compared with it
+        assert!(a.hash.is_some());
+
+        // Files from the first import are not reported as extra
+        let verify = crate::verify::verify_output(&output, &ThrottledProgress::new(&|_| {})).unwrap();
+        assert!(verify.is_ok(), "{:?}", verify);
+    }
+
+    #[test]
+    fn test_incremental_import_with_albums() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out");
+        let takeout = |name: &str, files: &[&str]| {
+            let path = dir.path().join(name);
+            let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+            for file in files {
+                zip.start_file(format!("Takeout/Google Photos/{}", file), SimpleFileOptions::default())
+                    .unwrap();
+                zip.write_all(file.rsplit('/').next().unwrap().as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+            path
+        };
+        let first = takeout("takeout-1.zip", &["Photos from 2023/a.jpg", "Trip/a.jpg"]);
+        let mut options = crate::ProcessOptions {
+            albums: true,
+            album_dest: "album".to_string(),
+            ..crate::ProcessOptions::for_test(&first, &output)
+        };
+        crate::process(&options, &|_| {}).unwrap();
+
+        // The newer Takeout repeats the album and adds a photo to it
+        let second = takeout(
+            "takeout-2.zip",
+            &["Photos from 2023/a.jpg", "Photos from 2023/c.jpg", "Trip/a.jpg", "Trip/c.jpg"],
+        );
+        options.zip_files = vec![second.to_string_lossy().to_string()];
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!((result.files_written, result.already_imported), (1, 1));
+
+        let mut album: Vec<_> = fs::read_dir(output.join("albums/Trip")).unwrap().map(|e| e.unwrap().file_name()).collect();
+        album.sort();
+        assert_eq!(album, ["a.jpg", "c.jpg"]);
+        let verify = crate::verify::verify_output(&output, &ThrottledProgress::new(&|_| {})).unwrap();
+        assert!(verify.is_ok(), "{:?}", verify);
+    }
+}
@@ -1,6 +1,7 @@
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct Media {
     /// Relative path inside the zip
@@ -13,7 +14,7 @@ pub struct Media {
     pub filename: String,
     /// File size in bytes
     pub size: u64,
-    /// SHA-256 hash hex (lazy, None if not computed or >64MiB)
+    /// Content hash hex (lazy, None if not computed)
     pub hash: Option<String>,
     /// Extracted date
     pub date: Option<NaiveDateTime>,
@@ -21,6 +22,8 @@ pub struct Media {
     pub date_accuracy: u8,
     /// Album names this media belongs to
     pub albums: Vec<String>,
+    /// Entry came from an album folder rather than a year folder
+    pub album_only: bool,
 }
 
 impl Media {
@@ -35,6 +38,7 @@ impl Media {
             date: None,
             date_accuracy: u8::MAX,
             albums: Vec::new(),
+            album_only: false,
         }
     }
 }
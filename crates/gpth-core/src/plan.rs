@@ -0,0 +1,261 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::DedupSummary;
+use crate::dedup::HashAlgorithm;
+use crate::media::Media;
+use crate::{sink, writer, ProcessOptions};
+
+/// The dated, deduplicated media a run will write, as returned by
+/// [`crate::scan`] and consumed by [`crate::execute`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    /// Input ZIPs; `Media::zip_index` points into this list
+    pub zip_files: Vec<String>,
+    /// Algorithm of the content hashes in `media`
+    pub hash_algorithm: HashAlgorithm,
+    /// Media to write. Entries may be removed, and their dates and album
+    /// names changed; the ZIP fields must stay as they are.
+    pub media: Vec<Media>,
+    /// Media found in the ZIPs, counting an album copy of a year-folder file once
+    pub total_media: u64,
+    pub duplicates_removed: u64,
+    pub warnings: Vec<String>,
+}
+
+impl Plan {
+    pub(crate) fn new(options: &ProcessOptions, media: Vec<Media>, summary: DedupSummary) -> Self {
+        Self {
+            zip_files: options.zip_files.clone(),
+            hash_algorithm: options.hash_algorithm,
+            media,
+            total_media: summary.total_media,
+            duplicates_removed: summary.duplicates_removed,
+            warnings: summary.warnings,
+        }
+    }
+
+    pub(crate) fn summary(&self) -> DedupSummary {
+        DedupSummary {
+            total_media: self.total_media,
+            duplicates_removed: self.duplicates_removed,
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    /// Names of all albums in the plan.
+    pub fn albums(&self) -> BTreeSet<&str> {
+        self.media
+            .iter()
+            .flat_map(|m| m.albums.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Rename an album, merging it into `to` if that album exists too.
+    /// Returns the number of media that were in it.
+    pub fn rename_album(&mut self, from: &str, to: &str) -> usize {
+        let mut renamed = 0;
+        for m in &mut self.media {
+            if !m.albums.iter().any(|a| a == from) {
+                continue;
+            }
+            m.albums.retain(|a| a != from);
+            if !m.albums.iter().any(|a| a == to) {
+                m.albums.push(to.to_string());
+            }
+            renamed += 1;
+        }
+        renamed
+    }
+
+    /// Output path of each media file, relative to the output, if `options`
+    /// wrote this plan to an empty output. Files already in the output can
+    /// shift numbered names (`name(1).jpg`) when the plan is executed.
+    pub fn output_paths(&self, options: &ProcessOptions) -> Vec<PathBuf> {
+        let output_dir = if options.output_format == sink::OutputFormat::Dir {
+            options.output.as_path()
+        } else {
+            Path::new("")
+        };
+        let write_options = crate::write_options(options);
+        let plan = writer::plan_paths(
+            &self.media,
+            &self.zip_files,
+            output_dir,
+            &write_options,
+            &HashMap::new(),
+            &HashMap::new(),
+            false,
+        );
+        plan.assignments
+            .into_iter()
+            .map(|p| p.strip_prefix(output_dir).map(Path::to_path_buf).unwrap_or(p))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_scan_edit_execute() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (name, data) in [
+            ("Photos from 2023/a.jpg", b"aaaa"),
+            ("Photos from 2023/b.jpg", b"bbbb"),
+            ("Photos from 2023/c.jpg", b"cccc"),
+            ("Trip/a.jpg", b"aaaa"),
+        ] {
+            zip.start_file(format!("Takeout/Google Photos/{}", name), SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let output = dir.path().join("out");
+        let options = ProcessOptions {
+            divide_to_dates: true,
+            albums: true,
+            ..ProcessOptions::for_test(&zip_path, &output)
+        };
+        let control = crate::ProcessControl::new();
+        let plan = crate::scan(&options, &control, &|_| {}).unwrap();
+        assert_eq!(plan.media.len(), 3);
+        assert_eq!(plan.albums(), BTreeSet::from(["Trip"]));
+        assert!(!output.join("date-unknown").exists());
+
+        // Survives a round trip, e.g. to a client that edits it
+        let mut plan: Plan = serde_json::from_str(&serde_json::to_string(&plan).unwrap()).unwrap();
+        plan.media.retain(|m| m.filename != "b.jpg");
+        let c = plan.media.iter_mut().find(|m| m.filename == "c.jpg").unwrap();
+        c.date = chrono::NaiveDate::from_ymd_opt(2023, 5, 1).unwrap().and_hms_opt(12, 0, 0);
+        assert_eq!(plan.rename_album("Trip", "Holiday"), 1);
+
+        let mut preview = plan.output_paths(&options);
+        preview.sort();
+        assert_eq!(
+            preview,
+            [PathBuf::from("2023/05/c.jpg"), PathBuf::from("date-unknown/a.jpg")]
+        );
+
+        let result = crate::execute(&options, plan, &control, &|_| {}).unwrap();
+        assert_eq!(result.files_written, 2);
+        assert!(output.join("2023/05/c.jpg").exists());
+        assert!(output.join("date-unknown/a.jpg").exists());
+        assert!(!output.join("date-unknown/b.jpg").exists());
+        let albums = fs::read_to_string(output.join("albums.json")).unwrap();
+        assert!(albums.contains("Holiday") && !albums.contains("Trip"), "{}", albums);
+        // Completed, so nothing is left to resume
+        assert!(crate::Checkpoint::load(&output).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_album_entries_take_dates_only_from_confirmed_copies() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let json = br#"{"photoTakenTime": {"timestamp": "1672574400"}}"#;
+        for (name, data) in [
+            ("Photos from 2023/a.jpg", &b"aaaa"[..]),
+            ("Photos from 2023/a.jpg.json", &json[..]),
+            ("Photos from 2023/b.jpg", b"bbbb"),
+            ("Photos from 2023/b.jpg.json", &json[..]),
+            // Same name and size as the year file, different content
+            ("Trip/a.jpg", b"xxxx"),
+            ("Trip/b.jpg", b"bbbb"),
+        ] {
+            zip.start_file(format!("Takeout/Google Photos/{}", name), SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let output = dir.path().join("out");
+        let options = ProcessOptions {
+            albums: true,
+            ..ProcessOptions::for_test(&zip_path, &output)
+        };
+        let plan = crate::scan(&options, &crate::ProcessControl::new(), &|_| {}).unwrap();
+        assert_eq!(plan.media.len(), 3);
+        let year_b = plan.media.iter().find(|m| m.filename == "b.jpg").unwrap();
+        assert!(year_b.date.is_some() && year_b.albums == ["Trip"]);
+        let album_a = plan.media.iter().find(|m| m.album_only).unwrap();
+        assert_eq!((album_a.filename.as_str(), album_a.date), ("a.jpg", None));
+    }
+
+    #[test]
+    fn test_execute_resumes_only_when_asked() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("Takeout/Google Photos/Photos from 2023/a.jpg", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"aaaa").unwrap();
+        zip.finish().unwrap();
+        let output = dir.path().join("out");
+        let options = ProcessOptions::for_test(&zip_path, &output);
+
+        // A checkpoint from an interrupted run whose file has since been deleted
+        let interrupted = || {
+            let control = crate::ProcessControl::new();
+            let plan = crate::scan(&options, &control, &|_| {}).unwrap();
+            let mut checkpoint = crate::Checkpoint::load(&output).unwrap().unwrap();
+            checkpoint.mark_written(&plan.media[0].zip_path, &output.join("a.jpg"), 4);
+            checkpoint.save(&output).unwrap();
+            plan
+        };
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let record = move |e: &crate::ProgressEvent| recorded.lock().unwrap().push(e.clone());
+        let resumed = crate::ProgressEvent::Resumed {
+            written_files: 1,
+            migrating: false,
+        };
+
+        let plan = interrupted();
+        let result = crate::execute(&options, plan, &crate::ProcessControl::new(), &record).unwrap();
+        assert!(result.drifted.is_empty());
+        assert!(!events.lock().unwrap().contains(&resumed));
+
+        fs::remove_file(output.join("a.jpg")).unwrap();
+        let plan = interrupted();
+        let control = crate::ProcessControl::new().with_resume(true);
+        let result = crate::execute(&options, plan, &control, &record).unwrap();
+        assert_eq!(result.drifted.len(), 1);
+        assert_eq!(result.files_written, 1);
+        assert!(events.lock().unwrap().contains(&resumed));
+    }
+
+    #[test]
+    fn test_execute_rejects_other_zips() {
+        let plan = Plan {
+            zip_files: vec!["a.zip".to_string()],
+            hash_algorithm: HashAlgorithm::Sha256,
+            media: Vec::new(),
+            total_media: 0,
+            duplicates_removed: 0,
+            warnings: Vec::new(),
+        };
+        let options: ProcessOptions = serde_json::from_value(serde_json::json!({
+            "zip_files": ["b.zip"],
+            "output": "out",
+            "divide_to_dates": false,
+            "skip_extras": false,
+            "no_guess": false,
+        }))
+        .unwrap();
+        let control = crate::ProcessControl::new();
+        let err = crate::execute(&options, plan, &control, &|_| {}).unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidPlan(_)), "{}", err);
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// A stage of processing (or of verifying an output directory).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Listing the entries of the input ZIPs
+    Scan,
+    /// Dates from JSON sidecars and file names
+    Date,
+    /// Dates read from EXIF
+    Exif,
+    /// Dates read from EXIF for entries found only in albums
+    AlbumExif,
+    /// Hashing files that share a size and removing duplicates
+    Dedup,
+    /// Matching media against files imported by earlier runs
+    Library,
+    /// Writing output files
+    Write,
+    /// Checking an output directory against its run report
+    Verify,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Scan => "scan",
+            Stage::Date => "date",
+            Stage::Exif => "exif",
+            Stage::AlbumExif => "album_exif",
+            Stage::Dedup => "dedup",
+            Stage::Library => "library",
+            Stage::Write => "write",
+            Stage::Verify => "verify",
+        }
+    }
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why a media file was not written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The checkpoint being resumed records it as written
+    Checkpoint,
+    /// An earlier run into the same output imported it
+    AlreadyImported,
+    /// The output already had it, as judged by `on_conflict`
+    Exists,
+}
+
+/// Something that happened while processing, reported through a
+/// [`ProgressCallback`]. Serialized with a `kind` tag for frontends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// A stage began; `total` is the number of items it will process
+    StageStarted { stage: Stage, total: u64 },
+    /// `current` of `total` items are done
+    Progress {
+        stage: Stage,
+        current: u64,
+        total: u64,
+        message: String,
+    },
+    /// `done` of `total` bytes are processed, with the smoothed throughput
+    /// and the estimated seconds left (unknown until bytes start flowing)
+    Bytes {
+        stage: Stage,
+        done: u64,
+        total: u64,
+        bytes_per_sec: f64,
+        eta_secs: Option<u64>,
+    },
+    /// A stage ended, with the bytes it processed and its average throughput
+    StageFinished {
+        stage: Stage,
+        elapsed_ms: u64,
+        bytes: u64,
+        bytes_per_sec: f64,
+    },
+    /// Work is on hold, e.g. until disk space is freed
+    Paused { stage: Stage, message: String },
+    /// Something went wrong without stopping the run
+    Warning { message: String },
+    /// A media file was not written
+    FileSkipped {
+        zip_path: String,
+        output_path: PathBuf,
+        reason: SkipReason,
+    },
+    /// The checkpoint was saved to disk, recording `written_files` files
+    CheckpointSaved { written_files: u64 },
+    /// An interrupted run's checkpoint was picked up; its `written_files`
+    /// are kept, after moving to their new paths when `migrating`
+    Resumed { written_files: u64, migrating: bool },
+    /// The `media` saved by the checkpoint after the stages before `stage`
+    /// were restored, so the run continues at `stage`
+    StageRestored { stage: Stage, media: u64 },
+}
+
+pub type ProgressCallback = dyn Fn(&ProgressEvent) + Send + Sync;
+
+/// Minimum time between two item (or byte) progress events.
+const THROTTLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time constant of the throughput average: samples older than this weigh
+/// about a third as much as the latest.
+const RATE_TIME_CONSTANT_SECS: f64 = 5.0;
+
+/// Exponential moving average of throughput, weighting a sample taken over
+/// `dt` seconds by how long it covers, so uneven intervals average fairly.
+fn smooth_rate(rate: Option<f64>, sample: f64, dt: f64) -> f64 {
+    match rate {
+        None => sample,
+        Some(rate) => rate + (sample - rate) * (1.0 - (-dt / RATE_TIME_CONSTANT_SECS).exp()),
+    }
+}
+
+/// Timing of a stage that has started.
+struct StageClock {
+    started: Instant,
+    /// Bytes processed so far
+    bytes: u64,
+    /// When throughput was last sampled, and the bytes processed by then
+    sample: (Instant, u64),
+    /// Smoothed throughput in bytes per second
+    rate: Option<f64>,
+}
+
+/// Throttled progress reporter — item and byte progress is emitted at most
+/// every 200ms or on completion; all other events go out immediately.
+pub struct ThrottledProgress<'a> {
+    inner: &'a ProgressCallback,
+    last_emit: Mutex<Instant>,
+    stages: Mutex<HashMap<Stage, StageClock>>,
+}
+
+impl<'a> ThrottledProgress<'a> {
+    pub fn new(inner: &'a ProgressCallback) -> Self {
+        Self {
+            inner,
+            last_emit: Mutex::new(Instant::now() - Duration::from_secs(1)),
+            stages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Pass an event straight to the callback.
+    pub fn emit(&self, event: ProgressEvent) {
+        (self.inner)(&event);
+    }
+
+    /// Whether progress may be emitted now; completion always may.
+    fn due(&self, is_done: bool) -> bool {
+        if is_done {
+            return true;
+        }
+        let mut last = self.last_emit.lock().unwrap();
+        if last.elapsed() < THROTTLE_INTERVAL {
+            return false;
+        }
+        *last = Instant::now();
+        true
+    }
+
+    /// Start timing `stage`.
+    pub fn start(&self, stage: Stage, total: u64) {
+        let now = Instant::now();
+        let clock = StageClock {
+            started: now,
+            bytes: 0,
+            sample: (now, 0),
+            rate: None,
+        };
+        self.stages.lock().unwrap().insert(stage, clock);
+        self.emit(ProgressEvent::StageStarted { stage, total });
+    }
+
+    /// Report that `current` of `total` items are done.
+    pub fn report(&self, stage: Stage, current: u64, total: u64, message: &str) {
+        if self.due(current >= total) {
+            self.emit(ProgressEvent::Progress {
+                stage,
+                current,
+                total,
+                message: message.to_string(),
+            });
+        }
+    }
+
+    /// Report items like `report`, together with `(done, total)` bytes.
+    /// Throughput and ETA come from a moving average over the samples taken
+    /// when progress is emitted.
+    pub fn report_bytes(&self, stage: Stage, current: u64, total: u64, (done, total_bytes): (u64, u64), message: &str) {
+        let is_done = current >= total;
+        {
+            let mut stages = self.stages.lock().unwrap();
+            if let Some(clock) = stages.get_mut(&stage) {
+                clock.bytes = clock.bytes.max(done);
+            }
+        }
+        if !self.due(is_done) {
+            return;
+        }
+
+        let mut bytes_per_sec = 0.0;
+        if let Some(clock) = self.stages.lock().unwrap().get_mut(&stage) {
+            let now = Instant::now();
+            let (at, bytes_then) = clock.sample;
+            let dt = now.duration_since(at).as_secs_f64();
+            // Too short an interval (the final report right after the last
+            // throttled one) would give a noisy sample
+            if dt >= THROTTLE_INTERVAL.as_secs_f64() / 4.0 || (clock.rate.is_none() && dt > 0.0) {
+                let sample = clock.bytes.saturating_sub(bytes_then) as f64 / dt;
+                clock.rate = Some(smooth_rate(clock.rate, sample, dt));
+                clock.sample = (now, clock.bytes);
+            }
+            bytes_per_sec = clock.rate.unwrap_or(0.0);
+        }
+        let remaining = total_bytes.saturating_sub(done);
+        let eta_secs = if is_done || remaining == 0 {
+            Some(0)
+        } else if bytes_per_sec > 0.0 {
+            Some((remaining as f64 / bytes_per_sec).ceil() as u64)
+        } else {
+            None
+        };
+
+        self.emit(ProgressEvent::Progress {
+            stage,
+            current,
+            total,
+            message: message.to_string(),
+        });
+        self.emit(ProgressEvent::Bytes {
+            stage,
+            done,
+            total: total_bytes,
+            bytes_per_sec,
+            eta_secs,
+        });
+    }
+
+    /// End `stage`, reporting how long it took.
+    pub fn finish(&self, stage: Stage) {
+        let (started, bytes) = self
+            .stages
+            .lock()
+            .unwrap()
+            .remove(&stage)
+            .map_or_else(|| (Instant::now(), 0), |clock| (clock.started, clock.bytes));
+        let elapsed = started.elapsed();
+        let bytes_per_sec = if elapsed.as_secs_f64() > 0.0 {
+            bytes as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        };
+        self.emit(ProgressEvent::StageFinished {
+            stage,
+            elapsed_ms: elapsed.as_millis() as u64,
+            bytes,
+            bytes_per_sec,
+        });
+    }
+
+    pub fn paused(&self, stage: Stage, message: &str) {
+        self.emit(ProgressEvent::Paused {
+            stage,
+            message: message.to_string(),
+        });
+    }
+
+    pub fn warning(&self, message: &str) {
+        self.emit(ProgressEvent::Warning {
+            message: message.to_string(),
+        });
+    }
+
+    pub fn file_skipped(&self, zip_path: &str, output_path: PathBuf, reason: SkipReason) {
+        self.emit(ProgressEvent::FileSkipped {
+            zip_path: zip_path.to_string(),
+            output_path,
+            reason,
+        });
+    }
+
+    pub fn checkpoint_saved(&self, written_files: u64) {
+        self.emit(ProgressEvent::CheckpointSaved { written_files });
+    }
+
+    pub fn resumed(&self, written_files: u64, migrating: bool) {
+        self.emit(ProgressEvent::Resumed { written_files, migrating });
+    }
+
+    pub fn stage_restored(&self, stage: Stage, media: u64) {
+        self.emit(ProgressEvent::StageRestored { stage, media });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let events = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let cb = move |e: &ProgressEvent| sink.lock().unwrap().push(e.clone());
+        let tp = ThrottledProgress::new(&cb);
+
+        tp.start(Stage::Write, 3);
+        tp.report_bytes(Stage::Write, 1, 3, (10, 30), "Writing files");
+        // Throttled: too soon after the last one and not done yet
+        tp.report_bytes(Stage::Write, 2, 3, (20, 30), "Writing files");
+        tp.report_bytes(Stage::Write, 3, 3, (30, 30), "Writing files");
+        tp.finish(Stage::Write);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[0], ProgressEvent::StageStarted { stage: Stage::Write, total: 3 });
+        assert!(matches!(events[1], ProgressEvent::Progress { current: 1, .. }));
+        assert!(matches!(events[3], ProgressEvent::Progress { current: 3, .. }));
+        assert!(matches!(
+            events[4],
+            ProgressEvent::Bytes {
+                done: 30,
+                total: 30,
+                eta_secs: Some(0),
+                ..
+            }
+        ));
+        assert!(matches!(events[5], ProgressEvent::StageFinished { stage: Stage::Write, bytes: 30, .. }));
+
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json, serde_json::json!({ "kind": "stage_started", "stage": "write", "total": 3 }));
+    }
+
+    #[test]
+    fn test_smooth_rate() {
+        assert_eq!(smooth_rate(None, 100.0, 0.2), 100.0);
+        // A short stall barely moves the average; a long one dominates it
+        let brief = smooth_rate(Some(100.0), 0.0, 0.2);
+        assert!(brief > 95.0 && brief < 100.0, "{}", brief);
+        let long = smooth_rate(Some(100.0), 0.0, 30.0);
+        assert!(long < 1.0, "{}", long);
+    }
+}
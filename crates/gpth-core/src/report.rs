@@ -0,0 +1,137 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::dedup::HashAlgorithm;
+use crate::media::Media;
+use crate::sanitize::RenamedName;
+use crate::writer::FailedItem;
+use crate::{Error, Result};
+
+/// Current report file format version
+const REPORT_VERSION: u32 = 1;
+
+/// Default report filename
+pub const REPORT_FILENAME: &str = ".gpth-report.json";
+
+/// A media entry and the output file it was written to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportedFile {
+    /// Index of the zip file in `RunReport::zip_files`
+    pub zip_index: usize,
+    /// Index of the entry within the zip archive
+    pub entry_index: usize,
+    /// Relative path inside the zip
+    pub zip_path: String,
+    /// Output path relative to the output directory
+    pub output_path: PathBuf,
+    pub size: u64,
+    /// Content hash, if computed during dedup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// Record of a completed run, stored as .gpth-report.json in the output directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub version: u32,
+    pub timestamp: DateTime<Utc>,
+    pub zip_files: Vec<String>,
+    pub hash_algorithm: HashAlgorithm,
+    /// Every non-duplicate media entry and its output file
+    pub files: Vec<ReportedFile>,
+    /// Album folder entries (copies or symlinks), relative to the output directory
+    #[serde(default)]
+    pub album_files: Vec<PathBuf>,
+    /// albums.json location, relative to the output directory when inside it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_json: Option<PathBuf>,
+    /// File and album names rewritten to be valid on the target filesystem
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub renamed: Vec<RenamedName>,
+    /// Output files of media an earlier run had already imported (also in `files`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub already_present: Vec<PathBuf>,
+    /// Media that could not be written. In tar output their entries are
+    /// still present, zero-filled or holding the bytes that failed the check.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FailedItem>,
+}
+
+impl RunReport {
+    /// Build a report from the final media list and its output assignments.
+    pub fn new(
+        zip_files: &[String],
+        hash_algorithm: HashAlgorithm,
+        media: &[Media],
+        assignments: &[PathBuf],
+        album_files: &[PathBuf],
+        album_json: Option<&Path>,
+        output_dir: &Path,
+    ) -> Self {
+        let files = media
+            .iter()
+            .zip(assignments.iter())
+            .map(|(m, dest)| ReportedFile {
+                zip_index: m.zip_index,
+                entry_index: m.entry_index,
+                zip_path: m.zip_path.clone(),
+                output_path: relative_to(dest, output_dir),
+                size: m.size,
+                hash: m.hash.clone(),
+            })
+            .collect();
+
+        Self {
+            version: REPORT_VERSION,
+            timestamp: Utc::now(),
+            zip_files: zip_files.to_vec(),
+            hash_algorithm,
+            files,
+            album_files: album_files.iter().map(|p| relative_to(p, output_dir)).collect(),
+            album_json: album_json.map(|p| relative_to(p, output_dir)),
+            renamed: Vec::new(),
+            already_present: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    /// Load the report from an output directory.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = output_dir.join(REPORT_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&path).map_err(Error::io(&path))?;
+        let reader = BufReader::new(file);
+        let report: RunReport = serde_json::from_reader(reader).map_err(|e| Error::CorruptFile {
+            path: path.clone(),
+            reason: e.to_string(),
+        })?;
+
+        Ok(Some(report))
+    }
+
+    /// Save the report to the output directory.
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = output_dir.join(REPORT_FILENAME);
+        let temp_path = output_dir.join(".gpth-report.tmp");
+
+        // Write to temp file first, then rename for atomicity
+        let file = File::create(&temp_path).map_err(Error::io(&temp_path))?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(writer, self).map_err(|e| Error::io(&temp_path)(e.into()))?;
+
+        fs::rename(&temp_path, &path).map_err(Error::io(&path))?;
+        Ok(())
+    }
+}
+
+/// Strip the output directory prefix, keeping absolute paths outside it as-is.
+fn relative_to(path: &Path, output_dir: &Path) -> PathBuf {
+    path.strip_prefix(output_dir).unwrap_or(path).to_path_buf()
+}
@@ -0,0 +1,588 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::sink::{http_error_kind, is_target_error, resolve_link, unix_time, uri_encode, ExistingEntry, OutputSink};
+
+/// Multipart part size; objects up to this size are sent with a single PUT.
+/// Also determines the ETag of multipart objects, see `content_etag`.
+pub const PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Bucket, key prefix and credentials for S3 output.
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    /// e.g. `http://localhost:9000`, without a trailing slash
+    pub endpoint: String,
+    pub bucket: String,
+    /// Key prefix: empty, or ending in `/`
+    pub prefix: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl S3Target {
+    /// Parse `s3://bucket/prefix`. Credentials and region come from the usual
+    /// AWS environment variables; the endpoint from `endpoint`, then
+    /// `AWS_ENDPOINT_URL`, then AWS itself.
+    pub fn from_output(output: &Path, endpoint: Option<&str>) -> anyhow::Result<Self> {
+        let url = output.to_string_lossy();
+        let Some(rest) = url.strip_prefix("s3://") else {
+            anyhow::bail!("S3 output must be given as s3://bucket/prefix, got {}", url);
+        };
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            anyhow::bail!("No bucket in {}", url);
+        }
+        let prefix = prefix.trim_matches('/');
+
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let region = env("AWS_REGION")
+            .or_else(|| env("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = endpoint
+            .map(str::to_string)
+            .or_else(|| env("AWS_ENDPOINT_URL"))
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let (Some(access_key), Some(secret_key)) = (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY")) else {
+            anyhow::bail!("Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY for S3 output");
+        };
+
+        Ok(Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            prefix: if prefix.is_empty() { String::new() } else { format!("{}/", prefix) },
+            region,
+            access_key,
+            secret_key,
+        })
+    }
+
+    fn host(&self) -> &str {
+        let rest = self.endpoint.split_once("://").map_or(self.endpoint.as_str(), |(_, r)| r);
+        rest.split('/').next().unwrap_or(rest)
+    }
+}
+
+/// Request body; streamed bodies are sent unsigned.
+enum Body<'a> {
+    Bytes(&'a [u8]),
+    Stream(&'a mut dyn Read, u64),
+}
+
+/// Uploads output files as objects, using path-style requests signed with
+/// AWS Signature Version 4 (works with AWS and MinIO alike). Files larger
+/// than `PART_SIZE` are streamed as multipart uploads, one part in memory at
+/// a time. Album links become server-side copies.
+pub struct S3Sink {
+    target: S3Target,
+    agent: ureq::Agent,
+}
+
+impl S3Sink {
+    pub fn new(target: S3Target) -> Self {
+        Self {
+            target,
+            agent: ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(30)).build(),
+        }
+    }
+
+    fn key(&self, path: &str) -> String {
+        format!("{}{}", self.target.prefix, path)
+    }
+
+    /// Send a signed request for `key` (the bucket itself when empty).
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: Body,
+    ) -> io::Result<ureq::Response> {
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let payload_hash = match &body {
+            Body::Bytes(data) => hex::encode(Sha256::digest(data)),
+            Body::Stream(..) => "UNSIGNED-PAYLOAD".to_string(),
+        };
+
+        let mut path = format!("/{}", uri_encode(&self.target.bucket, true));
+        if !key.is_empty() {
+            path = format!("{}/{}", path, uri_encode(key, false));
+        }
+        let mut query: Vec<(String, String)> =
+            query.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let mut signed: Vec<(String, String)> =
+            headers.iter().map(|(k, v)| (k.to_ascii_lowercase(), v.trim().to_string())).collect();
+        signed.push(("host".to_string(), self.target.host().to_string()));
+        signed.push(("x-amz-content-sha256".to_string(), payload_hash.clone()));
+        signed.push(("x-amz-date".to_string(), amz_date.clone()));
+        signed.sort();
+        let canonical_headers: String = signed.iter().map(|(k, v)| format!("{}:{}\n", k, v)).collect();
+        let signed_headers = signed.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.target.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut signing_key = hmac_sha256(format!("AWS4{}", self.target.secret_key).as_bytes(), date);
+        for part in [self.target.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac_sha256(&signing_key, part);
+        }
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.target.access_key,
+            scope,
+            signed_headers,
+            hex::encode(hmac_sha256(&signing_key, &string_to_sign))
+        );
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.target.endpoint, path)
+        } else {
+            format!("{}{}?{}", self.target.endpoint, path, query)
+        };
+        let mut request = self.agent.request(method, &url).set("Authorization", &authorization);
+        for (name, value) in signed.iter().filter(|(name, _)| name != "host") {
+            request = request.set(name, value);
+        }
+        let result = match body {
+            Body::Bytes(data) => request.send_bytes(data),
+            Body::Stream(reader, size) => request.set("Content-Length", &size.to_string()).send(reader),
+        };
+        result.map_err(|e| match e {
+            ureq::Error::Status(code, response) => io::Error::new(
+                http_error_kind(code),
+                format!(
+                    "S3 {} {}: HTTP {} {}",
+                    method,
+                    if key.is_empty() { &self.target.bucket } else { key },
+                    code,
+                    error_message(response)
+                ),
+            ),
+            ureq::Error::Transport(t) => io::Error::other(t.to_string()),
+        })
+    }
+
+    /// Stream `size` bytes as a multipart upload; aborted if any part fails.
+    fn put_multipart(&self, key: &str, headers: &[(&str, String)], size: u64, reader: &mut dyn Read) -> io::Result<()> {
+        let response = self.request("POST", key, &[("uploads", "")], headers, Body::Bytes(&[]))?;
+        let upload_id = xml_value(&read_body(response)?, "UploadId")
+            .ok_or_else(|| io::Error::other(format!("S3 did not start a multipart upload for {}", key)))?;
+
+        let result = self.upload_parts(key, &upload_id, size, reader);
+        if result.is_err() {
+            let _ = self.request("DELETE", key, &[("uploadId", &upload_id)], &[], Body::Bytes(&[]));
+        }
+        result
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, size: u64, reader: &mut dyn Read) -> io::Result<()> {
+        let mut buf = vec![0u8; size.min(PART_SIZE) as usize];
+        let mut parts = String::new();
+        let mut remaining = size;
+        let mut number = 1u32;
+        while remaining > 0 {
+            let len = remaining.min(PART_SIZE) as usize;
+            reader.read_exact(&mut buf[..len])?;
+            let part_number = number.to_string();
+            let response = self.request(
+                "PUT",
+                key,
+                &[("partNumber", &part_number), ("uploadId", upload_id)],
+                &[],
+                Body::Bytes(&buf[..len]),
+            )?;
+            let etag = response
+                .header("ETag")
+                .ok_or_else(|| io::Error::other(format!("S3 returned no ETag for part {} of {}", number, key)))?;
+            parts.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number,
+                xml_escape(etag)
+            ));
+            remaining -= len as u64;
+            number += 1;
+        }
+
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+        let response = self.request("POST", key, &[("uploadId", upload_id)], &[], Body::Bytes(body.as_bytes()))?;
+        check_body(key, response)
+    }
+}
+
+impl OutputSink for S3Sink {
+    fn add_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        reader: &mut dyn Read,
+    ) -> io::Result<()> {
+        let key = self.key(path);
+        let mut headers = vec![("content-type", mime_guess::from_path(path).first_or_octet_stream().to_string())];
+        // Same convention as rclone and s3fs, so sync tools keep the photo's date
+        if let Some(t) = mtime.and_then(unix_time) {
+            headers.push(("x-amz-meta-mtime", t.to_string()));
+        }
+        if size > PART_SIZE {
+            return self.put_multipart(&key, &headers, size, reader);
+        }
+        self.request("PUT", &key, &[], &headers, Body::Stream(reader, size))?;
+        Ok(())
+    }
+
+    /// Objects can't link to each other, so copy `target` server-side.
+    fn add_symlink(&mut self, path: &str, target: &str, _mtime: Option<NaiveDateTime>) -> io::Result<()> {
+        let source = resolve_link(path, target);
+        let copy_source = format!(
+            "/{}/{}",
+            uri_encode(&self.target.bucket, true),
+            uri_encode(&self.key(&source), false)
+        );
+        let key = self.key(path);
+        let response = self.request("PUT", &key, &[], &[("x-amz-copy-source", copy_source)], Body::Bytes(&[]))?;
+        check_body(&key, response)
+    }
+
+    /// Objects under the prefix, with sizes and ETags, via ListObjectsV2.
+    fn list_existing(&mut self) -> io::Result<Vec<ExistingEntry>> {
+        let mut entries = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.target.prefix.as_str())];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token));
+            }
+            let text = read_body(self.request("GET", "", &query, &[], Body::Bytes(&[]))?)?;
+            for contents in xml_elements(&text, "Contents") {
+                let Some(key) = xml_value(contents, "Key") else { continue };
+                let Some(path) = key.strip_prefix(&self.target.prefix).filter(|p| !p.is_empty()) else {
+                    continue;
+                };
+                entries.push(ExistingEntry {
+                    path: path.to_string(),
+                    size: xml_value(contents, "Size").and_then(|s| s.parse().ok()).unwrap_or(0),
+                    etag: xml_value(contents, "ETag").map(|e| e.trim_matches('"').to_string()),
+                    md5: None,
+                });
+            }
+            continuation = xml_value(&text, "NextContinuationToken");
+            if xml_value(&text, "IsTruncated").as_deref() != Some("true") || continuation.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn is_fatal(&self, e: &io::Error) -> bool {
+        is_target_error(e)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The ETag S3 gives `size` bytes of content uploaded by `S3Sink`: the MD5
+/// for a single PUT, or the MD5 of the part MD5s plus the part count for a
+/// multipart upload.
+pub(crate) fn content_etag(mut reader: impl Read, size: u64) -> io::Result<String> {
+    let mut part_digests = Vec::new();
+    let mut remaining = size;
+    loop {
+        let len = remaining.min(PART_SIZE);
+        let mut hasher = Md5::new();
+        let copied = io::copy(&mut (&mut reader).take(len), &mut hasher)?;
+        if copied != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry shorter than expected"));
+        }
+        part_digests.push(hasher.finalize());
+        remaining -= len;
+        if remaining == 0 {
+            break;
+        }
+    }
+    if size <= PART_SIZE {
+        return Ok(hex::encode(part_digests[0]));
+    }
+    let mut hasher = Md5::new();
+    for digest in &part_digests {
+        hasher.update(digest);
+    }
+    Ok(format!("{}-{}", hex::encode(hasher.finalize()), part_digests.len()))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn read_body(response: ureq::Response) -> io::Result<String> {
+    response.into_string()
+}
+
+/// Some S3 calls (copy, complete multipart) report failure in a 200 response body.
+fn check_body(key: &str, response: ureq::Response) -> io::Result<()> {
+    let text = read_body(response)?;
+    if text.contains("<Error>") {
+        let message = xml_value(&text, "Message").unwrap_or(text);
+        return Err(io::Error::other(format!("S3 {}: {}", key, message)));
+    }
+    Ok(())
+}
+
+fn error_message(response: ureq::Response) -> String {
+    let status = response.status_text().to_string();
+    response
+        .into_string()
+        .ok()
+        .and_then(|text| xml_value(&text, "Message"))
+        .unwrap_or(status)
+}
+
+/// Inner text of each `<tag>...</tag>` in `xml` (no nesting of the same tag).
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(&close) else { break };
+        found.push(&after[..end]);
+        rest = &after[end + close.len()..];
+    }
+    found
+}
+
+/// Unescaped text of the first `<tag>` in `xml`.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    xml_elements(xml, tag).first().map(|v| xml_unescape(v))
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#34;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::Media;
+    use crate::sanitize::{CaseSensitivity, NameProfile, NameRules};
+    use crate::writer::{ConflictPolicy, Durability, WriteOptions};
+    use crate::{HashAlgorithm, ThrottledProgress};
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    /// Stored objects: key -> (content, ETag, mtime metadata)
+    type Objects = Arc<Mutex<BTreeMap<String, (Vec<u8>, String, Option<String>)>>>;
+
+    fn percent_decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                out.push(u8::from_str_radix(&s[i + 1..i + 3], 16).unwrap());
+                i += 3;
+            } else {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Minimal in-memory stand-in for an S3 server (bucket "photos").
+    fn start_server() -> (String, Objects) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let objects: Objects = Arc::default();
+        let store = objects.clone();
+        std::thread::spawn(move || {
+            let mut parts: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+            for mut request in server.incoming_requests() {
+                let header = |name: &str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.to_string().eq_ignore_ascii_case(name))
+                        .map(|h| h.value.as_str().to_string())
+                };
+                let authorized = header("Authorization")
+                    .is_some_and(|a| a.starts_with("AWS4-HMAC-SHA256 Credential=test/"));
+                let copy_source = header("x-amz-copy-source");
+                let mtime = header("x-amz-meta-mtime");
+                let url = request.url().to_string();
+                let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+                let key = percent_decode(path.strip_prefix("/photos").unwrap().trim_start_matches('/'));
+                let query: HashMap<&str, String> = query
+                    .split('&')
+                    .filter_map(|kv| kv.split_once('='))
+                    .map(|(k, v)| (k, percent_decode(v)))
+                    .collect();
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).unwrap();
+
+                let mut objects = store.lock().unwrap();
+                let mut etag_header = None;
+                let reply = if !authorized {
+                    (403, "<Error><Message>denied</Message></Error>".to_string())
+                } else if query.get("list-type").is_some_and(|v| v == "2") {
+                    let prefix = &query["prefix"];
+                    let contents: String = objects
+                        .iter()
+                        .filter(|(k, _)| k.starts_with(prefix.as_str()))
+                        .map(|(k, (data, etag, _))| {
+                            format!(
+                                "<Contents><Key>{}</Key><Size>{}</Size><ETag>&quot;{}&quot;</ETag></Contents>",
+                                xml_escape(k),
+                                data.len(),
+                                etag
+                            )
+                        })
+                        .collect();
+                    (200, format!("<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>", contents))
+                } else if query.contains_key("uploads") {
+                    parts.clear();
+                    (200, "<InitiateMultipartUploadResult><UploadId>u1</UploadId></InitiateMultipartUploadResult>".into())
+                } else if let Some(number) = query.get("partNumber") {
+                    etag_header = Some(format!("\"{}\"", hex::encode(Md5::digest(&body))));
+                    parts.insert(number.parse().unwrap(), body);
+                    (200, String::new())
+                } else if query.contains_key("uploadId") && request.method().as_str() == "POST" {
+                    let data: Vec<u8> = parts.values().flatten().copied().collect();
+                    let mut hasher = Md5::new();
+                    for part in parts.values() {
+                        hasher.update(Md5::digest(part));
+                    }
+                    let etag = format!("{}-{}", hex::encode(hasher.finalize()), parts.len());
+                    objects.insert(key, (data, etag, mtime));
+                    (200, "<CompleteMultipartUploadResult/>".into())
+                } else if let Some(source) = copy_source {
+                    let source = percent_decode(source.strip_prefix("/photos/").unwrap());
+                    let object = objects[&source].clone();
+                    objects.insert(key, object);
+                    (200, "<CopyObjectResult/>".into())
+                } else if request.method().as_str() == "PUT" {
+                    let etag = hex::encode(Md5::digest(&body));
+                    objects.insert(key, (body, etag, mtime));
+                    (200, String::new())
+                } else {
+                    (400, String::new())
+                };
+                drop(objects);
+
+                let mut response = tiny_http::Response::from_string(reply.1).with_status_code(reply.0);
+                if let Some(etag) = etag_header {
+                    response.add_header(tiny_http::Header::from_bytes("ETag", etag).unwrap());
+                }
+                let _ = request.respond(response);
+            }
+        });
+        (url, objects)
+    }
+
+    #[test]
+    fn test_s3_upload_and_resume() {
+        let (url, objects) = start_server();
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let big = vec![7u8; PART_SIZE as usize + 1000];
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for (name, data) in [("a b.jpg", &b"small"[..]), ("big.mp4", &big[..])] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let date = chrono::NaiveDate::from_ymd_opt(2023, 5, 1).unwrap().and_hms_opt(12, 0, 0);
+        let mut small = Media::new(zip_paths[0].clone(), 0, 0, "a b.jpg".into(), 5);
+        small.date = date;
+        small.albums = vec!["Trip".into()];
+        let big_media = Media::new(zip_paths[0].clone(), 0, 1, "big.mp4".into(), big.len() as u64);
+        let media = [small, big_media];
+
+        let target = S3Target {
+            endpoint: url,
+            bucket: "photos".into(),
+            prefix: "takeout/".into(),
+            region: "us-east-1".into(),
+            access_key: "test".into(),
+            secret_key: "secret".into(),
+        };
+        let mut options = WriteOptions {
+            divide_to_dates: true,
+            album_dest: Some("album"),
+            album_link: true,
+            force: false,
+            durability: Durability::None,
+            min_free_bytes: 0,
+            names: NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, Path::new("")),
+            on_conflict: ConflictPolicy::Rename,
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        let callback = |_: &crate::ProgressEvent| {};
+        let progress = ThrottledProgress::new(&callback);
+
+        let mut sink = S3Sink::new(target.clone());
+        let result = crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+        assert_eq!((result.files_written, result.files_skipped), (2, 0));
+        {
+            let objects = objects.lock().unwrap();
+            let keys: Vec<&str> = objects.keys().map(String::as_str).collect();
+            assert_eq!(keys, ["takeout/2023/05/a b.jpg", "takeout/albums/Trip/a b.jpg", "takeout/date-unknown/big.mp4"]);
+            let (data, _, mtime) = &objects["takeout/2023/05/a b.jpg"];
+            assert_eq!(data, b"small");
+            assert_eq!(mtime.as_deref(), unix_time(date.unwrap()).map(|t| t.to_string()).as_deref());
+            assert_eq!(objects["takeout/albums/Trip/a b.jpg"].0, b"small");
+            assert_eq!(objects["takeout/date-unknown/big.mp4"].0, big);
+        }
+
+        // A second run finds everything by listing and comparing ETags
+        options.on_conflict = ConflictPolicy::Compare;
+        let mut sink = S3Sink::new(target);
+        let result = crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+        assert_eq!((result.files_written, result.files_skipped), (0, 2));
+        assert_eq!(objects.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("albums/Trip (1)/a b.jpg", false), "albums/Trip%20%281%29/a%20b.jpg");
+        assert_eq!(uri_encode("a/b~", true), "a%2Fb~");
+    }
+}
@@ -0,0 +1,376 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::writer::TEMP_SUFFIX;
+
+/// Filesystem a name must be valid on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NameProfile {
+    /// Linux/macOS filesystems: only `/` and NUL are illegal, 255 bytes per name
+    Posix,
+    /// NTFS and SMB shares: no `<>:"/\|?*` or control characters, no
+    /// trailing dots or spaces, no reserved device names like `CON`
+    Windows,
+    /// FAT32/exFAT USB drives: the Windows rules plus DEL and leading spaces
+    Fat,
+}
+
+impl Default for NameProfile {
+    /// The profile of the host OS.
+    fn default() -> Self {
+        if cfg!(windows) {
+            NameProfile::Windows
+        } else {
+            NameProfile::Posix
+        }
+    }
+}
+
+impl NameProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NameProfile::Posix => "posix",
+            NameProfile::Windows => "windows",
+            NameProfile::Fat => "fat",
+        }
+    }
+
+    /// Length of `name` in the unit the filesystem limits (bytes or UTF-16 units).
+    fn len_of(&self, name: &str) -> usize {
+        match self {
+            NameProfile::Posix => name.len(),
+            NameProfile::Windows | NameProfile::Fat => name.encode_utf16().count(),
+        }
+    }
+
+    fn is_illegal(&self, c: char) -> bool {
+        match self {
+            NameProfile::Posix => c == '/' || c == '\0',
+            NameProfile::Windows => c < ' ' || r#"<>:"/\|?*"#.contains(c),
+            NameProfile::Fat => c.is_ascii_control() || r#"<>:"/\|?*"#.contains(c),
+        }
+    }
+}
+
+impl std::fmt::Display for NameProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether output names that differ only in case refer to the same file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseSensitivity {
+    /// Probe the output directory (windows and fat profiles are always insensitive)
+    #[default]
+    Auto,
+    /// `IMG_1.JPG` and `img_1.jpg` are different files
+    Sensitive,
+    /// `IMG_1.JPG` and `img_1.jpg` are the same file
+    Insensitive,
+}
+
+impl CaseSensitivity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaseSensitivity::Auto => "auto",
+            CaseSensitivity::Sensitive => "sensitive",
+            CaseSensitivity::Insensitive => "insensitive",
+        }
+    }
+}
+
+impl std::fmt::Display for CaseSensitivity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Unicode normalization form applied to output names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NameNormalization {
+    /// Composed form (Windows, Linux and most NAS software expect this)
+    #[default]
+    Nfc,
+    /// Decomposed form (what macOS HFS+ stores)
+    Nfd,
+    /// Keep names exactly as they are in the Takeout
+    None,
+}
+
+impl NameNormalization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NameNormalization::Nfc => "nfc",
+            NameNormalization::Nfd => "nfd",
+            NameNormalization::None => "none",
+        }
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            NameNormalization::Nfc => name.nfc().collect(),
+            NameNormalization::Nfd => name.nfd().collect(),
+            NameNormalization::None => name.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for NameNormalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Check whether `dir` is on a case-insensitive filesystem by creating a
+/// probe file and looking it up with different case.
+pub fn is_case_insensitive(dir: &Path) -> std::io::Result<bool> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".gpth-case-probe");
+    fs::write(&probe, b"")?;
+    let insensitive = dir.join(".GPTH-CASE-PROBE").exists();
+    let _ = fs::remove_file(&probe);
+    Ok(insensitive)
+}
+
+/// Naming rules for one output directory.
+#[derive(Debug, Clone, Copy)]
+pub struct NameRules {
+    pub profile: NameProfile,
+    /// Names differing only in case (or Unicode form) collide
+    pub case_insensitive: bool,
+    pub normalization: NameNormalization,
+}
+
+impl NameRules {
+    /// Resolve `case` for `output_dir`. Auto detection falls back to
+    /// case-sensitive if the directory cannot be probed.
+    pub fn new(profile: NameProfile, case: CaseSensitivity, output_dir: &Path) -> Self {
+        let case_insensitive = match case {
+            CaseSensitivity::Sensitive => false,
+            CaseSensitivity::Insensitive => true,
+            CaseSensitivity::Auto => {
+                profile != NameProfile::Posix || is_case_insensitive(output_dir).unwrap_or(false)
+            }
+        };
+        Self {
+            profile,
+            case_insensitive,
+            normalization: NameNormalization::default(),
+        }
+    }
+
+    pub fn with_normalization(mut self, normalization: NameNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Apply the Unicode normalization form only.
+    pub fn normalize(&self, name: &str) -> String {
+        self.normalization.apply(name)
+    }
+
+    /// Normalize, then make the name valid for the profile.
+    pub fn sanitize(&self, name: &str) -> String {
+        sanitize_name(&self.normalize(name), self.profile)
+    }
+
+    pub fn numbered(&self, name: &str, counter: u32) -> String {
+        numbered_name(name, counter, self.profile)
+    }
+
+    /// Collision key for a name: the name itself, or its NFC case-folded form
+    /// on case-insensitive targets.
+    pub fn key(&self, name: &str) -> String {
+        if self.case_insensitive {
+            name.nfc().flat_map(char::to_lowercase).collect()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Collision key for a whole path.
+    pub fn path_key(&self, path: &Path) -> PathBuf {
+        if self.case_insensitive {
+            PathBuf::from(self.key(&path.to_string_lossy()))
+        } else {
+            path.to_path_buf()
+        }
+    }
+}
+
+/// What kind of name was rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameKind {
+    File,
+    Album,
+}
+
+/// A file or album name that was changed to be valid on the target filesystem.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenamedName {
+    pub kind: NameKind,
+    /// Name as found in the Takeout
+    pub original: String,
+    /// Name used in the output
+    pub renamed: String,
+}
+
+impl RenamedName {
+    pub fn file(original: &str, renamed: &str) -> Self {
+        Self {
+            kind: NameKind::File,
+            original: original.to_string(),
+            renamed: renamed.to_string(),
+        }
+    }
+
+    pub fn album(original: &str, renamed: &str) -> Self {
+        Self {
+            kind: NameKind::Album,
+            original: original.to_string(),
+            renamed: renamed.to_string(),
+        }
+    }
+}
+
+/// Longest name we produce: 255 minus room for the temporary-file prefix and suffix.
+const MAX_NAME_LEN: usize = 255 - 1 - TEMP_SUFFIX.len();
+
+/// Device names Windows reserves regardless of extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Rewrite a single path component (file or folder name) so it is valid on
+/// `profile`: illegal characters become `_`, reserved names get a `_` prefix,
+/// and over-long names are truncated keeping the extension.
+pub fn sanitize_name(name: &str, profile: NameProfile) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if profile.is_illegal(c) { '_' } else { c })
+        .collect();
+
+    if profile != NameProfile::Posix {
+        let trimmed = out.trim_end_matches(['.', ' ']);
+        out = if profile == NameProfile::Fat {
+            trimmed.trim_start_matches(' ')
+        } else {
+            trimmed
+        }
+        .to_string();
+
+        let stem = out.split('.').next().unwrap_or("");
+        if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem.trim_end())) {
+            out.insert(0, '_');
+        }
+    }
+
+    if out.is_empty() || out == "." || out == ".." {
+        out = "_".repeat(out.len().max(1));
+    }
+
+    let (stem, ext) = split_ext(&out);
+    fit(stem, ext, "", profile)
+}
+
+/// `name` with a collision counter, e.g. `IMG_1(2).jpg`, still within the
+/// profile's length limit.
+pub fn numbered_name(name: &str, counter: u32, profile: NameProfile) -> String {
+    let (stem, ext) = split_ext(name);
+    fit(stem, ext, &format!("({})", counter), profile)
+}
+
+/// Split off the extension (including the dot); dotfiles have none.
+fn split_ext(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    }
+}
+
+/// Join stem, suffix and extension, truncating the stem to fit.
+fn fit(stem: &str, ext: &str, suffix: &str, profile: NameProfile) -> String {
+    // An absurdly long "extension" is just part of the name
+    if profile.len_of(ext) > MAX_NAME_LEN / 2 {
+        return fit(&format!("{}{}", stem, ext), "", suffix, profile);
+    }
+    let budget = MAX_NAME_LEN.saturating_sub(profile.len_of(ext) + profile.len_of(suffix));
+    let mut kept = String::new();
+    let mut used = 0;
+    for c in stem.chars() {
+        let len = profile.len_of(c.encode_utf8(&mut [0; 4]));
+        if used + len > budget {
+            break;
+        }
+        used += len;
+        kept.push(c);
+    }
+    if profile != NameProfile::Posix && kept.len() < stem.len() {
+        // Truncation can expose a trailing dot or space
+        kept.truncate(kept.trim_end_matches(['.', ' ']).len());
+    }
+    format!("{}{}{}", kept, suffix, ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_name() {
+        use NameProfile::*;
+        assert_eq!(sanitize_name("VID:x?.mp4", Posix), "VID:x?.mp4");
+        assert_eq!(sanitize_name("VID:x?.mp4", Windows), "VID_x_.mp4");
+        assert_eq!(sanitize_name("Trip \"2023\"...", Windows), "Trip _2023_");
+        assert_eq!(sanitize_name("con.jpg", Windows), "_con.jpg");
+        assert_eq!(sanitize_name("Console.jpg", Windows), "Console.jpg");
+        assert_eq!(sanitize_name("  Beach", Fat), "Beach");
+        assert_eq!(sanitize_name("..", Windows), "_");
+
+        let long = format!("{}.jpg", "写".repeat(200));
+        let posix = sanitize_name(&long, Posix);
+        assert!(posix.len() <= MAX_NAME_LEN && posix.ends_with(".jpg"));
+        let windows = sanitize_name(&long, Windows);
+        assert_eq!(windows, long);
+
+        let numbered = numbered_name(&posix, 12, Posix);
+        assert!(numbered.len() <= MAX_NAME_LEN && numbered.ends_with("(12).jpg"));
+        assert_eq!(numbered_name("IMG_1.jpg", 2, Windows), "IMG_1(2).jpg");
+    }
+
+    #[test]
+    fn test_case_insensitive_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = NameRules::new(NameProfile::Fat, CaseSensitivity::Auto, dir.path());
+        assert!(rules.case_insensitive);
+        assert_eq!(rules.key("IMG_1.JPG"), rules.key("img_1.jpg"));
+        // NFD "é" and NFC "é" are the same name
+        assert_eq!(rules.key("Cafe\u{301}.jpg"), rules.key("CAF\u{c9}.JPG"));
+
+        let strict = NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, dir.path());
+        assert_ne!(strict.key("IMG_1.JPG"), strict.key("img_1.jpg"));
+        // The probe file is cleaned up
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_normalization() {
+        let dir = tempfile::tempdir().unwrap();
+        let nfd = "\u{30cf}\u{309a}\u{30ea}.jpg"; // パリ with a combining mark
+        let rules = NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, dir.path());
+        assert_eq!(rules.sanitize(nfd), "\u{30d1}\u{30ea}.jpg");
+        let keep = rules.with_normalization(NameNormalization::None);
+        assert_eq!(keep.sanitize(nfd), nfd);
+        let decomposed = rules.with_normalization(NameNormalization::Nfd);
+        assert_eq!(decomposed.sanitize("\u{30d1}\u{30ea}.jpg"), nfd);
+    }
+}
@@ -0,0 +1,958 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::checkpoint::{CancellationToken, CancelledError};
+use crate::media::Media;
+use crate::writer::{self, ContentComparer, ExistingFile, FailedItem, FailureCause, WriteOptions, WriteResult, TEMP_SUFFIX};
+use crate::{SkipReason, Stage, ThrottledProgress};
+
+/// Where the organized output goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// A directory tree (resumable)
+    #[default]
+    Dir,
+    /// A single ZIP file; media are stored, not recompressed
+    Zip,
+    /// A single (uncompressed) tar file
+    Tar,
+    /// Objects in an S3-compatible bucket (`s3://bucket/prefix`)
+    #[cfg(feature = "s3")]
+    S3,
+    /// Files in a WebDAV collection such as Nextcloud (`https://...`)
+    #[cfg(feature = "webdav")]
+    Webdav,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Dir => "dir",
+            OutputFormat::Zip => "zip",
+            OutputFormat::Tar => "tar",
+            #[cfg(feature = "s3")]
+            OutputFormat::S3 => "s3",
+            #[cfg(feature = "webdav")]
+            OutputFormat::Webdav => "webdav",
+        }
+    }
+
+    /// Zip or tar: a single local file.
+    pub fn is_archive(&self) -> bool {
+        matches!(self, OutputFormat::Zip | OutputFormat::Tar)
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Destination that output files are streamed into, one at a time.
+/// Paths are relative and `/`-separated.
+pub trait OutputSink {
+    /// Add a regular file of `size` bytes read from `reader`. If the reader
+    /// fails, the entry is dropped where the format allows it.
+    fn add_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        reader: &mut dyn Read,
+    ) -> io::Result<()>;
+
+    /// Add a symbolic link at `path` pointing to `target` (relative to the link's
+    /// directory). Targets without links store a copy of `target` instead.
+    fn add_symlink(&mut self, path: &str, target: &str, mtime: Option<NaiveDateTime>) -> io::Result<()>;
+
+    /// Files already at the target from an earlier run. Fresh archives have none.
+    fn list_existing(&mut self) -> io::Result<Vec<ExistingEntry>> {
+        Ok(Vec::new())
+    }
+
+    /// Whether an error from `add_file` or `add_symlink` ends the run. An
+    /// archive is broken by any failed write; a remote target only by
+    /// errors that every later file would hit too.
+    fn is_fatal(&self, _e: &io::Error) -> bool {
+        true
+    }
+
+    /// Write any trailing structures (central directory, end blocks) and flush.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// A file found at a sink's target by `OutputSink::list_existing`.
+#[derive(Debug, Clone)]
+pub struct ExistingEntry {
+    pub path: String,
+    pub size: u64,
+    /// Server-side content tag (S3 ETag), compared by `--on-conflict compare`
+    pub etag: Option<String>,
+    /// MD5 of the content from the server's checksums (WebDAV `oc:checksums`),
+    /// compared when there is no ETag
+    pub md5: Option<String>,
+}
+
+/// ZIP archive sink. Entries use the Stored method: photos and videos are
+/// already compressed, so deflating them again only costs time.
+pub struct ZipSink<W: Write + Seek> {
+    zip: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> ZipSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            zip: ZipWriter::new(inner),
+        }
+    }
+
+    fn options(size: u64, mtime: Option<NaiveDateTime>) -> SimpleFileOptions {
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(0o644)
+            .large_file(size >= u32::MAX as u64);
+        if let Some(dt) = mtime.and_then(zip_datetime) {
+            options = options.last_modified_time(dt);
+        }
+        options
+    }
+}
+
+/// Media dates are local time; stored as seconds since the epoch like file mtimes.
+pub(crate) fn unix_time(dt: NaiveDateTime) -> Option<i64> {
+    Some(dt.and_local_timezone(chrono::Local).single()?.timestamp())
+}
+
+/// ZIP timestamps cover 1980..=2107; anything else keeps the writer's default.
+fn zip_datetime(dt: NaiveDateTime) -> Option<zip::DateTime> {
+    zip::DateTime::from_date_and_time(
+        u16::try_from(dt.year()).ok()?,
+        dt.month() as u8,
+        dt.day() as u8,
+        dt.hour() as u8,
+        dt.minute() as u8,
+        dt.second() as u8,
+    )
+    .ok()
+}
+
+impl<W: Write + Seek> OutputSink for ZipSink<W> {
+    fn add_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        reader: &mut dyn Read,
+    ) -> io::Result<()> {
+        self.zip.start_file(path, Self::options(size, mtime)).map_err(io::Error::other)?;
+        if let Err(e) = io::copy(reader, &mut self.zip) {
+            // Drop the partial entry so the archive holds only complete files
+            let _ = self.zip.abort_file();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn add_symlink(&mut self, path: &str, target: &str, mtime: Option<NaiveDateTime>) -> io::Result<()> {
+        self.zip
+            .add_symlink(path, target, Self::options(0, mtime))
+            .map_err(io::Error::other)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let mut inner = self.zip.finish().map_err(io::Error::other)?;
+        inner.flush()
+    }
+}
+
+/// POSIX (pax) tar sink. Long or non-ASCII paths and files of 8 GiB or more
+/// get a pax extended header.
+///
+/// The header goes out before the data, so an entry whose source fails
+/// cannot be dropped: it is zero-filled to its declared size to keep the
+/// following entries readable, and stays in the archive under its name.
+/// `write_media` lists such entries as failed items in the run report.
+pub struct TarSink<W: Write> {
+    out: W,
+}
+
+const TAR_BLOCK: usize = 512;
+/// Largest value of an 11-digit octal header field (8 GiB - 1).
+const TAR_MAX_SIZE: u64 = 0o777_7777_7777;
+
+impl<W: Write> TarSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    fn write_header(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        kind: u8,
+        link: &str,
+    ) -> io::Result<()> {
+        let mtime = mtime.and_then(unix_time).map(|t| t.max(0) as u64).unwrap_or(0);
+
+        // Fields that don't fit the ustar header go into a pax record first
+        let mut pax = String::new();
+        let (name, prefix) = split_ustar_path(path).unwrap_or_else(|| {
+            pax_record(&mut pax, "path", path);
+            (ascii_fallback(path, 100), String::new())
+        });
+        if link.len() > 100 || !link.is_ascii() {
+            pax_record(&mut pax, "linkpath", link);
+        }
+        if size > TAR_MAX_SIZE {
+            pax_record(&mut pax, "size", &size.to_string());
+        }
+        if !pax.is_empty() {
+            let pax_name = format!("PaxHeaders/{}", ascii_fallback(&name, 80));
+            self.write_raw_header(&pax_name, "", pax.len() as u64, mtime, b'x', "")?;
+            self.out.write_all(pax.as_bytes())?;
+            self.pad(pax.len() as u64)?;
+        }
+
+        let header_size = if size > TAR_MAX_SIZE { 0 } else { size };
+        self.write_raw_header(&name, &prefix, header_size, mtime, kind, &ascii_fallback(link, 100))
+    }
+
+    fn write_raw_header(
+        &mut self,
+        name: &str,
+        prefix: &str,
+        size: u64,
+        mtime: u64,
+        kind: u8,
+        link: &str,
+    ) -> io::Result<()> {
+        let mut h = [0u8; TAR_BLOCK];
+        put(&mut h[0..100], name.as_bytes());
+        put(&mut h[100..108], format!("{:07o}", if kind == b'2' { 0o777 } else { 0o644 }).as_bytes());
+        put(&mut h[108..116], b"0000000");
+        put(&mut h[116..124], b"0000000");
+        put(&mut h[124..136], format!("{:011o}", size).as_bytes());
+        put(&mut h[136..148], format!("{:011o}", mtime.min(TAR_MAX_SIZE)).as_bytes());
+        h[148..156].fill(b' ');
+        h[156] = kind;
+        put(&mut h[157..257], link.as_bytes());
+        put(&mut h[257..265], b"ustar\x0000");
+        put(&mut h[345..500], prefix.as_bytes());
+        let checksum: u32 = h.iter().map(|&b| b as u32).sum();
+        put(&mut h[148..156], format!("{:06o}\0 ", checksum).as_bytes());
+        self.out.write_all(&h)
+    }
+
+    /// Zero-fill up to the next block boundary after `len` bytes of data.
+    fn pad(&mut self, len: u64) -> io::Result<()> {
+        let rem = (len % TAR_BLOCK as u64) as usize;
+        if rem != 0 {
+            self.out.write_all(&[0u8; TAR_BLOCK][..TAR_BLOCK - rem])?;
+        }
+        Ok(())
+    }
+}
+
+fn put(field: &mut [u8], value: &[u8]) {
+    let n = value.len().min(field.len());
+    field[..n].copy_from_slice(&value[..n]);
+}
+
+/// Split `path` into ustar `name` (<= 100 bytes) and `prefix` (<= 155 bytes),
+/// or None if it needs a pax header.
+fn split_ustar_path(path: &str) -> Option<(String, String)> {
+    if !path.is_ascii() {
+        return None;
+    }
+    if path.len() <= 100 {
+        return Some((path.to_string(), String::new()));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+        .map(|(prefix, name)| (name.to_string(), prefix.to_string()))
+}
+
+/// ASCII stand-in for readers without pax support.
+fn ascii_fallback(s: &str, max: usize) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii() && c != '\0' { c } else { '_' })
+        .take(max)
+        .collect()
+}
+
+/// Append a pax record: "<len> <key>=<value>\n", where <len> counts itself.
+fn pax_record(out: &mut String, key: &str, value: &str) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    out.push_str(&format!("{} {}={}\n", len, key, value));
+}
+
+impl<W: Write> OutputSink for TarSink<W> {
+    fn add_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        reader: &mut dyn Read,
+    ) -> io::Result<()> {
+        self.write_header(path, size, mtime, b'0', "")?;
+        // The header promised `size` bytes; a short or failing read is
+        // zero-filled so the rest of the archive stays readable
+        let mut out = CountingWriter::new(&mut self.out);
+        let result = match io::copy(&mut reader.take(size), &mut out) {
+            Ok(n) if n < size => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry shorter than expected")),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let written = out.count;
+        if result.is_err() {
+            io::copy(&mut io::repeat(0).take(size - written), &mut self.out)?;
+        }
+        self.pad(size)?;
+        result
+    }
+
+    fn add_symlink(&mut self, path: &str, target: &str, mtime: Option<NaiveDateTime>) -> io::Result<()> {
+        self.write_header(path, 0, mtime, b'2', target)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.out.write_all(&[0u8; TAR_BLOCK * 2])?;
+        self.out.flush()
+    }
+}
+
+/// Writer adapter that counts the bytes actually accepted by `inner`.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader over a ZIP entry that remembers whether the source (not the sink)
+/// failed and computes the CRC32 of the bytes it hands out.
+struct SourceReader<R> {
+    inner: R,
+    failed: bool,
+    expected_crc: u32,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> SourceReader<R> {
+    fn new(inner: R, expected_crc: u32) -> Self {
+        Self {
+            inner,
+            failed: false,
+            expected_crc,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Check the bytes the sink took against the entry's CRC32. Sinks that
+    /// stop at the declared size (tar) never reach the end of the entry, so
+    /// the zip reader's own checksum check would not run.
+    fn verify(mut self) -> Result<(), FailureCause> {
+        let mut probe = [0u8; 1];
+        match self.inner.read(&mut probe) {
+            Ok(0) => {}
+            Ok(_) => {
+                return Err(FailureCause::CorruptEntry {
+                    message: "entry is longer than its recorded size".to_string(),
+                })
+            }
+            // The zip reader's own checksum check; reported below with both values
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(e) => return Err(FailureCause::CorruptEntry { message: e.to_string() }),
+        }
+        let actual = self.hasher.finalize();
+        if actual != self.expected_crc {
+            return Err(FailureCause::ChecksumMismatch {
+                expected: self.expected_crc,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SourceReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf).inspect_err(|_| self.failed = true)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters
+/// (and `/` unless `encode_slash`), as SigV4 requires
+/// and WebDAV servers accept.
+#[cfg(any(feature = "s3", feature = "webdav"))]
+pub(crate) fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Path that a link at `path` with relative `target` points to.
+#[cfg(any(feature = "s3", feature = "webdav"))]
+pub(crate) fn resolve_link(path: &str, target: &str) -> String {
+    let mut resolved: Vec<&str> = path.split('/').collect();
+    resolved.pop();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                resolved.pop();
+            }
+            "." | "" => {}
+            s => resolved.push(s),
+        }
+    }
+    resolved.join("/")
+}
+
+/// Kind of the error for an HTTP error status, so `is_target_error` can
+/// tell a refused login or missing bucket from a one-off failure.
+#[cfg(any(feature = "s3", feature = "webdav"))]
+pub(crate) fn http_error_kind(code: u16) -> io::ErrorKind {
+    match code {
+        401 | 403 => io::ErrorKind::PermissionDenied,
+        404 => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    }
+}
+
+/// `OutputSink::is_fatal` for remote targets: a refused login or a missing
+/// bucket or collection fails every file, anything else just this one.
+#[cfg(any(feature = "s3", feature = "webdav"))]
+pub(crate) fn is_target_error(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound)
+}
+
+/// `/`-separated form of a relative output path or link target.
+pub(crate) fn archive_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy()),
+            Component::ParentDir => Some("..".into()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A zip or tar file on local disk. The archive is built under a temporary
+/// name and only renamed into place by `finish`; dropping an unfinished
+/// writer removes it.
+pub struct ArchiveWriter {
+    sink: Option<Box<dyn OutputSink>>,
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl ArchiveWriter {
+    /// Start a new archive at `path`.
+    pub fn create(path: &Path, format: OutputFormat) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(TEMP_SUFFIX);
+        let temp_path = path.with_file_name(temp_name);
+        let file = File::create(&temp_path)?;
+        let sink: Box<dyn OutputSink> = match format {
+            OutputFormat::Zip => Box::new(ZipSink::new(BufWriter::new(file))),
+            OutputFormat::Tar => Box::new(TarSink::new(BufWriter::new(file))),
+            _ => anyhow::bail!("{} is not an archive format", format),
+        };
+        Ok(Self {
+            sink: Some(sink),
+            temp_path,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Directory whose free space the archive consumes.
+    pub fn space_dir(&self) -> &Path {
+        match self.temp_path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        }
+    }
+
+    fn sink(&mut self) -> &mut dyn OutputSink {
+        self.sink.as_deref_mut().expect("archive already finished")
+    }
+}
+
+impl OutputSink for ArchiveWriter {
+    fn add_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        reader: &mut dyn Read,
+    ) -> io::Result<()> {
+        self.sink().add_file(path, size, mtime, reader)
+    }
+
+    fn add_symlink(&mut self, path: &str, target: &str, mtime: Option<NaiveDateTime>) -> io::Result<()> {
+        self.sink().add_symlink(path, target, mtime)
+    }
+
+    /// Complete the archive and move it to its final name.
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if let Some(sink) = self.sink.take() {
+            sink.finish()?;
+        }
+        fs::rename(&self.temp_path, &self.path)
+    }
+}
+
+impl Drop for ArchiveWriter {
+    fn drop(&mut self) {
+        if self.sink.is_some() {
+            self.sink = None;
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Add a small generated file (albums.json, the run report).
+pub fn add_bytes(sink: &mut dyn OutputSink, path: &str, data: &[u8]) -> io::Result<()> {
+    let now = chrono::Local::now().naive_local();
+    sink.add_file(path, data.len() as u64, Some(now), &mut &data[..])
+}
+
+/// Stream every media file into `sink`, plus album entries when `album_dest`
+/// is "album": links with `album_link`, copies otherwise. Names follow the
+/// same rules and collision handling as directory output, including
+/// `on_conflict` against the files `sink` reports as already present.
+/// `space_dir` is checked for free space when the sink writes to local disk.
+pub fn write_media(
+    sink: &mut dyn OutputSink,
+    media: &[Media],
+    zip_paths: &[String],
+    options: &WriteOptions,
+    space_dir: Option<&Path>,
+    progress: &ThrottledProgress,
+    cancel_token: Option<&CancellationToken>,
+) -> anyhow::Result<WriteResult> {
+    let names = options.names;
+    // Map: path key -> existing file
+    let existing: HashMap<PathBuf, ExistingFile> = if options.force {
+        HashMap::new()
+    } else {
+        sink.list_existing()?
+            .into_iter()
+            .map(|e| {
+                let path = PathBuf::from(e.path);
+                let file = ExistingFile {
+                    path: path.clone(),
+                    size: e.size,
+                    remote: true,
+                    etag: e.etag,
+                    md5: e.md5,
+                };
+                (names.path_key(&path), file)
+            })
+            .collect()
+    };
+
+    let plan = writer::plan_paths(media, zip_paths, Path::new(""), options, &HashMap::new(), &existing, false);
+    let mut renamed = plan.renamed;
+    let mut failed: Vec<(usize, FailedItem)> = plan.failed;
+
+    // Album entries: (media index, path in output, already present)
+    let mut album_entries: Vec<(usize, PathBuf, bool)> = Vec::new();
+    if options.album_dest == Some("album") {
+        let album_dirs = writer::assign_album_folders(media, names, &mut renamed);
+        let mut used: HashSet<PathBuf> = HashSet::new();
+        let mut comparer = ContentComparer::new(zip_paths, options.hash_algorithm);
+        for (idx, m) in media.iter().enumerate() {
+            for album_name in &m.albums {
+                let album_dir = Path::new("albums").join(&album_dirs[album_name.as_str()]);
+                let (entry, present) =
+                    writer::plan_album_entry(m, &album_dir, options, &mut used, &mut comparer, |_, key| {
+                        existing.get(key).cloned()
+                    });
+                album_entries.push((idx, entry, present));
+            }
+        }
+    }
+
+    // Primary files first, each source ZIP read front to back, then album copies
+    let mut order: Vec<usize> = (0..media.len())
+        .filter(|i| !plan.skip_indices.contains(i) && !failed.iter().any(|(f, _)| f == i))
+        .collect();
+    order.sort_by_key(|&i| (media[i].zip_index, media[i].entry_index));
+    let primary_count = order.len();
+    let mut jobs: Vec<(usize, PathBuf, bool)> = order.into_iter().map(|i| (i, plan.assignments[i].clone(), true)).collect();
+    if !options.album_link {
+        jobs.extend(
+            album_entries
+                .iter()
+                .filter(|(_, _, present)| !present)
+                .map(|(i, p, _)| (*i, p.clone(), false)),
+        );
+    }
+
+    for &i in &plan.skip_indices {
+        progress.file_skipped(&media[i].zip_path, plan.assignments[i].clone(), SkipReason::Exists);
+    }
+
+    let total = jobs.len() as u64;
+    let total_bytes: u64 = jobs.iter().map(|(i, _, _)| media[*i].size).sum();
+    if let Some(dir) = space_dir {
+        crate::disk_space::ensure_space(dir, total_bytes, options.min_free_bytes)?;
+    }
+    progress.start(Stage::Write, total);
+
+    let mut archives: HashMap<usize, Option<ZipArchive<File>>> = HashMap::new();
+    let mut current = 0u64;
+    let mut bytes = 0u64;
+    let mut primary_failed = 0usize;
+    for (idx, dest, is_primary) in jobs {
+        if let Some(token) = cancel_token {
+            token.check()?;
+        }
+        let m = &media[idx];
+        // Album copies of a file that failed would fail the same way
+        if !is_primary && failed.iter().any(|(i, _)| *i == idx) {
+            continue;
+        }
+        if let Some(dir) = space_dir {
+            crate::disk_space::wait_for_space(dir, m.size, options.min_free_bytes, cancel_token, progress)
+                .map_err(|_| CancelledError)?;
+        }
+
+        let archive = archives.entry(m.zip_index).or_insert_with(|| {
+            File::open(&zip_paths[m.zip_index]).ok().and_then(|f| ZipArchive::new(f).ok())
+        });
+        let result = match archive {
+            None => Err(FailureCause::CorruptEntry {
+                message: format!("cannot open {}", zip_paths[m.zip_index]),
+            }),
+            Some(archive) => match archive.by_index(m.entry_index) {
+                Err(e) => Err(FailureCause::from_zip(&e)),
+                Ok(entry) => {
+                    let expected_crc = entry.crc32();
+                    let mut reader = SourceReader::new(entry, expected_crc);
+                    match sink.add_file(&archive_path(&dest), m.size, m.date, &mut reader) {
+                        Ok(()) => reader.verify(),
+                        Err(e) if reader.failed => Err(FailureCause::CorruptEntry { message: e.to_string() }),
+                        Err(e) if sink.is_fatal(&e) => return Err(e.into()),
+                        Err(e) => Err(FailureCause::Network { message: e.to_string() }),
+                    }
+                }
+            },
+        };
+        if let Err(cause) = result {
+            if is_primary {
+                primary_failed += 1;
+            }
+            failed.push((idx, FailedItem::new(zip_paths, m, &dest, cause)));
+        }
+
+        current += 1;
+        bytes += m.size;
+        progress.report_bytes(Stage::Write, current, total, (bytes, total_bytes), "Writing files");
+    }
+    progress.finish(Stage::Write);
+
+    let failed_indices: HashSet<usize> = failed.iter().map(|(i, _)| *i).collect();
+    let mut album_files = Vec::new();
+    for (idx, entry, present) in album_entries {
+        if failed_indices.contains(&idx) {
+            continue;
+        }
+        if options.album_link && !present {
+            let dest = &plan.assignments[idx];
+            let target = pathdiff::diff_paths(dest, entry.parent().unwrap_or(Path::new("")))
+                .unwrap_or_else(|| dest.clone());
+            match sink.add_symlink(&archive_path(&entry), &archive_path(&target), media[idx].date) {
+                Ok(()) => {}
+                Err(e) if sink.is_fatal(&e) => return Err(e.into()),
+                Err(e) => {
+                    let cause = FailureCause::Network { message: e.to_string() };
+                    failed.push((idx, FailedItem::new(zip_paths, &media[idx], &entry, cause)));
+                    continue;
+                }
+            }
+        }
+        album_files.push(entry);
+    }
+
+    Ok(WriteResult {
+        assignments: plan.assignments,
+        files_written: (primary_count - primary_failed) as u64,
+        files_skipped: plan.skip_indices.len() as u64,
+        files_moved: 0,
+        renamed,
+        album_files,
+        failed: failed.into_iter().map(|(_, item)| item).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::HashAlgorithm;
+    use crate::sanitize::{CaseSensitivity, NameProfile, NameRules};
+    use crate::writer::{ConflictPolicy, Durability};
+
+    /// (path, data) of each regular file in a tar stream.
+    fn read_tar(buf: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut files = Vec::new();
+        let mut pos = 0;
+        while pos + TAR_BLOCK <= buf.len() && buf[pos..pos + TAR_BLOCK].iter().any(|&b| b != 0) {
+            let h = &buf[pos..pos + TAR_BLOCK];
+            let name = String::from_utf8_lossy(&h[..100]).trim_end_matches('\0').to_string();
+            let size = u64::from_str_radix(std::str::from_utf8(&h[124..135]).unwrap(), 8).unwrap() as usize;
+            pos += TAR_BLOCK;
+            if h[156] == b'0' {
+                files.push((name, buf[pos..pos + size].to_vec()));
+            }
+            pos += size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        }
+        files
+    }
+
+    /// Yields `good` bytes, then fails.
+    struct FailingReader {
+        good: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.good == 0 {
+                return Err(io::Error::other("read failed"));
+            }
+            let n = self.good.min(buf.len());
+            buf[..n].fill(1);
+            self.good -= n;
+            Ok(n)
+        }
+    }
+
+    fn test_write_options(on_conflict: ConflictPolicy) -> WriteOptions<'static> {
+        WriteOptions {
+            divide_to_dates: false,
+            album_dest: Some("album"),
+            album_link: false,
+            force: false,
+            durability: Durability::None,
+            min_free_bytes: 0,
+            names: NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, Path::new("")),
+            on_conflict,
+            hash_algorithm: HashAlgorithm::Sha256,
+        }
+    }
+
+    fn write_stored_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, data) in files {
+            zip.start_file(*name, stored).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_tar_sink_layout() {
+        let mut buf = Vec::new();
+        let long = format!("albums/{}/photo.jpg", "旅".repeat(60));
+        {
+            let mut sink = Box::new(TarSink::new(&mut buf));
+            sink.add_file("a.jpg", 5, None, &mut &b"hello"[..]).unwrap();
+            sink.add_file(&long, 2, None, &mut &b"hi"[..]).unwrap();
+            sink.add_symlink("albums/x/a.jpg", "../../a.jpg", None).unwrap();
+            sink.finish().unwrap();
+        }
+        // header + 1 data block, pax header + pax data + header + data, symlink header, 2 end blocks
+        assert_eq!(buf.len(), TAR_BLOCK * 9);
+        assert_eq!(&buf[..5], b"a.jpg");
+        assert_eq!(&buf[257..262], b"ustar");
+        assert_eq!(&buf[TAR_BLOCK..TAR_BLOCK + 5], b"hello");
+        assert_eq!(buf[TAR_BLOCK * 2 + 156], b'x');
+        let pax = String::from_utf8_lossy(&buf[TAR_BLOCK * 3..TAR_BLOCK * 4]);
+        assert!(pax.contains(&format!("path={}\n", long)));
+        assert_eq!(buf[TAR_BLOCK * 6 + 156], b'2');
+
+        // Header checksums are valid
+        for block in [0, 2, 4, 6] {
+            let h = &buf[block * TAR_BLOCK..(block + 1) * TAR_BLOCK];
+            let sum: u32 = h.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { 32 } else { b as u32 }).sum();
+            let stored = u32::from_str_radix(std::str::from_utf8(&h[148..154]).unwrap(), 8).unwrap();
+            assert_eq!(sum, stored);
+        }
+    }
+
+    #[test]
+    fn test_pax_record_length() {
+        let mut out = String::new();
+        pax_record(&mut out, "path", "a");
+        assert_eq!(out, "9 path=a\n");
+        let mut out = String::new();
+        pax_record(&mut out, "path", &"x".repeat(92));
+        assert_eq!(out.len(), 102);
+        assert!(out.starts_with("102 "));
+    }
+
+    #[test]
+    fn test_tar_sink_failed_read_keeps_alignment() {
+        let mut buf = Vec::new();
+        {
+            let mut sink = Box::new(TarSink::new(&mut buf));
+            sink.add_file("a.jpg", 5, None, &mut &b"hello"[..]).unwrap();
+            assert!(sink.add_file("bad.jpg", 1000, None, &mut FailingReader { good: 600 }).is_err());
+            sink.add_file("c.jpg", 3, None, &mut &b"end"[..]).unwrap();
+            sink.finish().unwrap();
+        }
+        // 3 headers, 1 + 2 + 1 data blocks, 2 end blocks
+        assert_eq!(buf.len(), TAR_BLOCK * 9);
+        let files = read_tar(&buf);
+        let names: Vec<&str> = files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["a.jpg", "bad.jpg", "c.jpg"]);
+        assert_eq!(files[0].1, b"hello");
+        assert_eq!(files[1].1.len(), 1000);
+        assert!(files[1].1[600..].iter().all(|&b| b == 0));
+        assert_eq!(files[2].1, b"end");
+    }
+
+    #[test]
+    fn test_tar_output_reports_corrupt_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_stored_zip(&zip_path, &[("a.jpg", b"original photo bytes"), ("b.jpg", b"second")]);
+        // Flip one byte of the first entry's stored data
+        let mut bytes = fs::read(&zip_path).unwrap();
+        let pos = bytes.windows(8).position(|w| w == b"original").unwrap();
+        bytes[pos] ^= 0xff;
+        fs::write(&zip_path, &bytes).unwrap();
+
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let media = [
+            Media::new("a.jpg".into(), 0, 0, "a.jpg".into(), 20),
+            Media::new("b.jpg".into(), 0, 1, "b.jpg".into(), 6),
+        ];
+        let callback = |_: &crate::ProgressEvent| {};
+        let progress = ThrottledProgress::new(&callback);
+        let mut buf = Vec::new();
+        let result = {
+            let mut sink = TarSink::new(&mut buf);
+            let options = test_write_options(ConflictPolicy::Rename);
+            let result = write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+            Box::new(sink).finish().unwrap();
+            result
+        };
+
+        assert_eq!(result.files_written, 1);
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].zip_path, "a.jpg");
+        assert!(matches!(result.failed[0].cause, FailureCause::ChecksumMismatch { .. }));
+        let files = read_tar(&buf);
+        assert_eq!(files[1], ("b.jpg".to_string(), b"second".to_vec()));
+    }
+
+    /// Sink that records added paths and reports a fixed set of existing files.
+    struct RecordingSink {
+        existing: Vec<ExistingEntry>,
+        added: Vec<String>,
+    }
+
+    impl OutputSink for RecordingSink {
+        fn add_file(&mut self, path: &str, _: u64, _: Option<NaiveDateTime>, reader: &mut dyn Read) -> io::Result<()> {
+            io::copy(reader, &mut io::sink())?;
+            self.added.push(path.to_string());
+            Ok(())
+        }
+
+        fn add_symlink(&mut self, path: &str, _: &str, _: Option<NaiveDateTime>) -> io::Result<()> {
+            self.added.push(path.to_string());
+            Ok(())
+        }
+
+        fn list_existing(&mut self) -> io::Result<Vec<ExistingEntry>> {
+            Ok(self.existing.clone())
+        }
+
+        fn finish(self: Box<Self>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_album_entries_follow_conflict_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_stored_zip(&zip_path, &[("a.jpg", b"photo")]);
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let mut m = Media::new("a.jpg".into(), 0, 0, "a.jpg".into(), 5);
+        m.albums = vec!["Trip".into()];
+        let media = [m];
+        let callback = |_: &crate::ProgressEvent| {};
+        let progress = ThrottledProgress::new(&callback);
+
+        // Both the primary file and its album copy exist with a different size
+        let run = |policy: ConflictPolicy| {
+            let mut sink = RecordingSink {
+                existing: ["a.jpg", "albums/Trip/a.jpg"]
+                    .into_iter()
+                    .map(|p| ExistingEntry { path: p.into(), size: 99, etag: None, md5: None })
+                    .collect(),
+                added: Vec::new(),
+            };
+            let result = write_media(&mut sink, &media, &zip_paths, &test_write_options(policy), None, &progress, None)
+                .unwrap();
+            (sink.added, result.album_files)
+        };
+
+        let (added, album_files) = run(ConflictPolicy::Skip);
+        assert!(added.is_empty());
+        assert_eq!(album_files, [PathBuf::from("albums/Trip/a.jpg")]);
+
+        let (added, _) = run(ConflictPolicy::Rename);
+        assert_eq!(added, ["a(1).jpg", "albums/Trip/a(1).jpg"]);
+
+        let (added, _) = run(ConflictPolicy::Overwrite);
+        assert_eq!(added, ["a.jpg", "albums/Trip/a.jpg"]);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use zip::ZipArchive;
+
+use crate::checkpoint::Checkpoint;
+use crate::dedup::{compute_streaming_hash, HashAlgorithm};
+use crate::library::Library;
+use crate::report::{ReportedFile, RunReport};
+use crate::{Stage, ThrottledProgress};
+
+/// Outcome of verifying an output directory against its source ZIPs.
+#[derive(Debug, Default)]
+pub struct VerifyResult {
+    /// Number of output files checked
+    pub checked: u64,
+    /// Expected output files that do not exist
+    pub missing: Vec<PathBuf>,
+    /// Output files whose size differs from the ZIP entry
+    pub truncated: Vec<PathBuf>,
+    /// Output files whose content hash differs from the ZIP entry
+    pub mismatched: Vec<PathBuf>,
+    /// Files in the output directory that no run produced
+    pub extra: Vec<PathBuf>,
+    /// Entries that could not be checked (unreadable ZIP or output file)
+    pub errors: Vec<String>,
+    /// Verification was done against an interrupted run's checkpoint
+    /// (existence and size only, no extra-file detection)
+    pub incomplete_run: bool,
+}
+
+impl VerifyResult {
+    /// True if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+            && self.truncated.is_empty()
+            && self.mismatched.is_empty()
+            && self.extra.is_empty()
+            && self.errors.is_empty()
+            && !self.incomplete_run
+    }
+}
+
+/// Verify that every non-duplicate media entry recorded for `output_dir` has
+/// an output file with matching size and hash, and that no extra files exist.
+///
+/// Uses the run report of a completed run. If only a checkpoint exists (the
+/// run was interrupted), the files written so far are checked by size.
+pub fn verify_output(output_dir: &Path, progress: &ThrottledProgress) -> crate::Result<VerifyResult> {
+    let Some(report) = RunReport::load(output_dir)? else {
+        return match Checkpoint::load(output_dir)? {
+            Some(checkpoint) => Ok(verify_checkpoint(&checkpoint, output_dir)),
+            None => Err(crate::Error::NothingToVerify {
+                path: output_dir.to_path_buf(),
+            }),
+        };
+    };
+
+    let mut result = VerifyResult::default();
+    let total = report.files.len() as u64;
+    let counter = AtomicU64::new(0);
+
+    // Size check first; only files of the right size are worth hashing
+    let mut to_hash: HashMap<usize, Vec<&ReportedFile>> = HashMap::new();
+    for f in &report.files {
+        let path = output_dir.join(&f.output_path);
+        match fs::metadata(&path) {
+            Err(_) => result.missing.push(f.output_path.clone()),
+            Ok(meta) if meta.len() != f.size => result.truncated.push(f.output_path.clone()),
+            Ok(_) => to_hash.entry(f.zip_index).or_default().push(f),
+        }
+    }
+    result.checked = total;
+    progress.start(Stage::Verify, total);
+
+    let num_threads = rayon::current_num_threads();
+    for (zip_idx, files) in &to_hash {
+        let Some(zip_path) = report.zip_files.get(*zip_idx) else {
+            result.errors.push(format!("Report refers to unknown zip index {}", zip_idx));
+            continue;
+        };
+        let chunk_size = files.len().div_ceil(num_threads);
+
+        let chunk_results: Vec<(Vec<PathBuf>, Vec<String>)> = std::thread::scope(|s| {
+            let handles: Vec<_> = files
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let counter = &counter;
+                    let algorithm = report.hash_algorithm;
+                    s.spawn(move || -> (Vec<PathBuf>, Vec<String>) {
+                        let mut mismatched = Vec::new();
+                        let mut errors = Vec::new();
+                        let mut archive = match File::open(zip_path)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|f| Ok(ZipArchive::new(f)?))
+                        {
+                            Ok(a) => a,
+                            Err(e) => {
+                                errors.push(format!("Cannot open {}: {}", zip_path, e));
+                                return (mismatched, errors);
+                            }
+                        };
+                        for f in chunk {
+                            match check_hash(&mut archive, f, &output_dir.join(&f.output_path), algorithm) {
+                                Ok(true) => {}
+                                Ok(false) => mismatched.push(f.output_path.clone()),
+                                Err(e) => errors.push(format!("{}: {}", f.output_path.display(), e)),
+                            }
+                            let current = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                            progress.report(Stage::Verify, current, total, "Verifying files");
+                        }
+                        (mismatched, errors)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for (mismatched, errors) in chunk_results {
+            result.mismatched.extend(mismatched);
+            result.errors.extend(errors);
+        }
+    }
+
+    progress.finish(Stage::Verify);
+
+    // Anything else in the output directory is extra
+    let mut expected: HashSet<PathBuf> = report.files.iter().map(|f| f.output_path.clone()).collect();
+    expected.extend(report.album_files.iter().cloned());
+    expected.extend(report.album_json.iter().cloned());
+    // Media imported by earlier runs that this run's ZIPs did not contain
+    if let Some(library) = Library::load(output_dir)? {
+        expected.extend(library.files.into_iter().map(|f| f.output_path));
+    }
+    let mut existing = Vec::new();
+    collect_files(output_dir, output_dir, &mut existing);
+    result.extra = existing
+        .into_iter()
+        .filter(|p| !expected.contains(p) && !is_gpth_metadata(p))
+        .collect();
+
+    result.missing.sort();
+    result.truncated.sort();
+    result.mismatched.sort();
+    result.extra.sort();
+    Ok(result)
+}
+
+/// Compare the content hash of an output file with its ZIP entry.
+fn check_hash(
+    archive: &mut ZipArchive<File>,
+    f: &ReportedFile,
+    output_path: &Path,
+    algorithm: HashAlgorithm,
+) -> anyhow::Result<bool> {
+    // Dedup hashes were computed from the ZIP entry; reuse them when present
+    let expected = match &f.hash {
+        Some(hash) => hash.clone(),
+        None => compute_streaming_hash(archive.by_index(f.entry_index)?, algorithm)?,
+    };
+    let actual = compute_streaming_hash(File::open(output_path)?, algorithm)?;
+    Ok(expected == actual)
+}
+
+/// Check the files recorded by an interrupted run's checkpoint (existence and size).
+fn verify_checkpoint(checkpoint: &Checkpoint, output_dir: &Path) -> VerifyResult {
+    let mut result = VerifyResult {
+        incomplete_run: true,
+        ..Default::default()
+    };
+    for f in &checkpoint.written_files {
+        let relative = f.output_path.strip_prefix(output_dir).unwrap_or(&f.output_path).to_path_buf();
+        match fs::metadata(output_dir.join(&relative)) {
+            Err(_) => result.missing.push(relative),
+            Ok(meta) if meta.len() != f.size => result.truncated.push(relative),
+            Ok(_) => {}
+        }
+        result.checked += 1;
+    }
+    result
+}
+
+/// Recursively collect files (and symlinks) relative to `root`.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            collect_files(root, &path, files);
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.push(relative.to_path_buf());
+        }
+    }
+}
+
+/// Files gpth keeps in the output root for its own bookkeeping.
+fn is_gpth_metadata(relative: &Path) -> bool {
+    relative.parent().is_some_and(|p| p.as_os_str().is_empty())
+        && relative
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with(".gpth-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_verify_detects_problems() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (name, data) in [
+            ("Takeout/Google Photos/Photos from 2023/a.jpg", &b"aaaa"[..]),
+            ("Takeout/Google Photos/Photos from 2023/b.jpg", &b"bbbbbb"[..]),
+            ("Takeout/Google Photos/Photos from 2023/c.jpg", &b"cccc"[..]),
+        ] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let output = dir.path().join("out");
+        let options = crate::ProcessOptions::for_test(&zip_path, &output);
+        crate::process(&options, &|_| {}).unwrap();
+
+        let cb = |_: &crate::ProgressEvent| {};
+        let tp = ThrottledProgress::new(&cb);
+        let clean = verify_output(&output, &tp).unwrap();
+        assert!(clean.is_ok(), "{:?}", clean);
+        assert_eq!(clean.checked, 3);
+
+        fs::write(output.join("a.jpg"), b"aa").unwrap();
+        fs::write(output.join("c.jpg"), b"xxxx").unwrap();
+        fs::remove_file(output.join("b.jpg")).unwrap();
+        fs::write(output.join("stray.jpg"), b"zz").unwrap();
+
+        let broken = verify_output(&output, &tp).unwrap();
+        assert!(!broken.is_ok());
+        assert_eq!(broken.truncated, vec![PathBuf::from("a.jpg")]);
+        assert_eq!(broken.missing, vec![PathBuf::from("b.jpg")]);
+        assert_eq!(broken.mismatched, vec![PathBuf::from("c.jpg")]);
+        assert_eq!(broken.extra, vec![PathBuf::from("stray.jpg")]);
+    }
+}
@@ -0,0 +1,537 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use base64::Engine;
+use chrono::NaiveDateTime;
+use regex::Regex;
+
+use crate::sink::{http_error_kind, is_target_error, resolve_link, unix_time, uri_encode, ExistingEntry, OutputSink};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns"><d:prop><d:resourcetype/><d:getcontentlength/><oc:checksums/></d:prop></d:propfind>"#;
+
+/// Collection URL and credentials for WebDAV output.
+#[derive(Debug, Clone)]
+pub struct WebDavTarget {
+    /// e.g. `https://cloud.example.com/remote.php/dav/files/alice/Photos`, without a trailing slash
+    pub url: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+impl WebDavTarget {
+    /// Take the collection from `output` (an http(s) URL) and the login from
+    /// `WEBDAV_USER` / `WEBDAV_PASSWORD`.
+    pub fn from_output(output: &Path) -> anyhow::Result<Self> {
+        let url = output.to_string_lossy();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            anyhow::bail!("WebDAV output must be an http(s) URL, got {}", url);
+        }
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            user: env("WEBDAV_USER"),
+            password: env("WEBDAV_PASSWORD"),
+        })
+    }
+
+    /// Path part of `url`, percent-decoded, without a trailing slash.
+    fn base_path(&self) -> String {
+        let rest = self.url.split_once("://").map_or(self.url.as_str(), |(_, r)| r);
+        let path = rest.find('/').map_or("", |i| &rest[i..]);
+        percent_decode(path).trim_end_matches('/').to_string()
+    }
+}
+
+/// Uploads output files to a WebDAV collection (Nextcloud, ownCloud, Apache
+/// mod_dav, ...). Directories are created with MKCOL, files streamed with
+/// PUT, and the photo's date sent as `X-OC-Mtime`, which Nextcloud and
+/// ownCloud apply as the file's modification time. Album links become
+/// server-side COPYs.
+pub struct WebDavSink {
+    target: WebDavTarget,
+    agent: ureq::Agent,
+    authorization: Option<String>,
+    /// Collections known to exist, relative to the target
+    collections: HashSet<String>,
+}
+
+impl WebDavSink {
+    pub fn new(target: WebDavTarget) -> Self {
+        let authorization = target.user.as_ref().map(|user| {
+            let login = format!("{}:{}", user, target.password.as_deref().unwrap_or(""));
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(login))
+        });
+        Self {
+            target,
+            agent: ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(30)).build(),
+            authorization,
+            collections: HashSet::new(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        if path.is_empty() {
+            format!("{}/", self.target.url)
+        } else {
+            format!("{}/{}", self.target.url, uri_encode(path, false))
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self.agent.request(method, &self.url(path));
+        match &self.authorization {
+            Some(auth) => request.set("Authorization", auth),
+            None => request,
+        }
+    }
+
+    /// Create the collection `dir` and any missing parents up to the target
+    /// itself, once each.
+    fn ensure_collection(&mut self, dir: &str) -> io::Result<()> {
+        if self.collections.contains(dir) {
+            return Ok(());
+        }
+        if !dir.is_empty() {
+            self.ensure_collection(dir.rsplit_once('/').map_or("", |(parent, _)| parent))?;
+        }
+        match self.request("MKCOL", dir).call() {
+            // 405: already exists
+            Ok(_) | Err(ureq::Error::Status(405, _)) => {}
+            Err(e) => return Err(to_io_error("MKCOL", dir, e)),
+        }
+        self.collections.insert(dir.to_string());
+        Ok(())
+    }
+
+    fn ensure_parent(&mut self, path: &str) -> io::Result<()> {
+        self.ensure_collection(path.rsplit_once('/').map_or("", |(dir, _)| dir))
+    }
+
+    /// Direct members of collection `dir`, each with whether it is a
+    /// collection. ownCloud and Nextcloud report checksums as
+    /// `<oc:checksum>SHA1:... MD5:... ADLER32:...</oc:checksum>`; Nextcloud
+    /// only has them for files uploaded with one, other servers never do.
+    fn propfind(&self, dir: &str) -> io::Result<Vec<(ExistingEntry, bool)>> {
+        let response = match self
+            .request("PROPFIND", dir)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY)
+        {
+            Ok(response) => response,
+            // Nothing uploaded yet
+            Err(ureq::Error::Status(404, _)) if dir.is_empty() => return Ok(Vec::new()),
+            Err(e) => return Err(to_io_error("PROPFIND", dir, e)),
+        };
+        let text = response.into_string()?;
+
+        let base = self.target.base_path();
+        let [response_re, href_re, type_re, length_re, checksum_re] =
+            ["response", "href", "resourcetype", "getcontentlength", "checksum"].map(element_re);
+        let mut members = Vec::new();
+        for response in elements(&response_re, &text) {
+            let Some(href) = elements(&href_re, response).first().map(|h| percent_decode(h.trim())) else {
+                continue;
+            };
+            // Some servers answer with absolute URLs
+            let href = match href.split_once("://") {
+                Some((_, rest)) => rest.find('/').map_or(String::new(), |i| rest[i..].to_string()),
+                None => href,
+            };
+            let Some(relative) = href.strip_prefix(&base) else { continue };
+            let relative = relative.trim_matches('/');
+            if relative.is_empty() || relative == dir {
+                continue;
+            }
+            let is_collection = elements(&type_re, response)
+                .first()
+                .is_some_and(|t| t.contains("collection"));
+            let size = elements(&length_re, response)
+                .first()
+                .and_then(|s| s.trim().parse().ok())
+                .unwrap_or(0);
+            let md5 = elements(&checksum_re, response)
+                .iter()
+                .flat_map(|c| c.split_whitespace())
+                .find_map(|c| c.split_once(':').filter(|(kind, _)| kind.eq_ignore_ascii_case("MD5")))
+                .map(|(_, value)| value.to_ascii_lowercase());
+            let entry = ExistingEntry {
+                path: relative.to_string(),
+                size,
+                etag: None,
+                md5,
+            };
+            members.push((entry, is_collection));
+        }
+        Ok(members)
+    }
+}
+
+impl OutputSink for WebDavSink {
+    fn add_file(
+        &mut self,
+        path: &str,
+        size: u64,
+        mtime: Option<NaiveDateTime>,
+        reader: &mut dyn Read,
+    ) -> io::Result<()> {
+        self.ensure_parent(path)?;
+        let mut request = self
+            .request("PUT", path)
+            .set("Content-Type", mime_guess::from_path(path).first_or_octet_stream().as_ref())
+            .set("Content-Length", &size.to_string());
+        if let Some(t) = mtime.and_then(unix_time) {
+            request = request.set("X-OC-Mtime", &t.to_string());
+        }
+        request.send(reader).map_err(|e| to_io_error("PUT", path, e))?;
+        Ok(())
+    }
+
+    /// WebDAV has no portable links, so COPY `target` server-side.
+    fn add_symlink(&mut self, path: &str, target: &str, _mtime: Option<NaiveDateTime>) -> io::Result<()> {
+        self.ensure_parent(path)?;
+        let source = resolve_link(path, target);
+        let destination = self.url(path);
+        self.request("COPY", &source)
+            .set("Destination", &destination)
+            .set("Overwrite", "T")
+            .call()
+            .map_err(|e| to_io_error("COPY", path, e))?;
+        Ok(())
+    }
+
+    /// Files under the target collection, found with Depth: 1 PROPFINDs
+    /// (many servers refuse Depth: infinity).
+    fn list_existing(&mut self) -> io::Result<Vec<ExistingEntry>> {
+        let mut entries = Vec::new();
+        let mut pending = VecDeque::from([String::new()]);
+        while let Some(dir) = pending.pop_front() {
+            let members = self.propfind(&dir)?;
+            if dir.is_empty() && !members.is_empty() {
+                self.collections.insert(dir.clone());
+            }
+            for (entry, is_collection) in members {
+                if is_collection {
+                    self.collections.insert(entry.path.clone());
+                    pending.push_back(entry.path);
+                } else {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn is_fatal(&self, e: &io::Error) -> bool {
+        is_target_error(e)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn to_io_error(method: &str, path: &str, e: ureq::Error) -> io::Error {
+    match e {
+        ureq::Error::Status(code, response) => io::Error::new(
+            http_error_kind(code),
+            format!("WebDAV {} {}: HTTP {} {}", method, path, code, response.status_text()),
+        ),
+        ureq::Error::Transport(t) => io::Error::other(t.to_string()),
+    }
+}
+
+/// Matches elements with local name `name`, whatever their namespace prefix.
+fn element_re(name: &str) -> Regex {
+    Regex::new(&format!(r"(?s)<(?:[\w.-]+:)?{0}(?:\s[^>]*)?>(.*?)</(?:[\w.-]+:)?{0}>", name))
+        .expect("valid element pattern")
+}
+
+/// Inner text of each element matched by `re`.
+fn elements<'a>(re: &Regex, xml: &'a str) -> Vec<&'a str> {
+    re.captures_iter(xml).map(|c| c.get(1).map_or("", |m| m.as_str())).collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::Media;
+    use crate::sanitize::{CaseSensitivity, NameProfile, NameRules};
+    use crate::writer::{ConflictPolicy, Durability, FailureCause, WriteOptions};
+    use crate::{HashAlgorithm, ThrottledProgress};
+    use md5::Digest;
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    /// Server state: path -> None for collections, Some((content, X-OC-Mtime)) for files
+    type Tree = Arc<Mutex<BTreeMap<String, Option<(Vec<u8>, Option<String>)>>>>;
+
+    /// Minimal in-memory WebDAV server rooted at /dav/files/me/Photos.
+    fn start_server() -> (String, Tree) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dav/files/me/Photos", server.server_addr().to_ip().unwrap());
+        let tree: Tree = Arc::default();
+        tree.lock().unwrap().insert(String::new(), None);
+        let store = tree.clone();
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let header = |name: &str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.to_string().eq_ignore_ascii_case(name))
+                        .map(|h| h.value.as_str().to_string())
+                };
+                let authorized = header("Authorization").as_deref() == Some("Basic bWU6cHc="); // me:pw
+                let mtime = header("X-OC-Mtime");
+                let destination = header("Destination");
+                let to_path = |url: &str| {
+                    let url = url.split_once("/Photos").map_or("", |(_, p)| p);
+                    percent_decode(url).trim_matches('/').to_string()
+                };
+                let path = to_path(request.url());
+                let parent = path.rsplit_once('/').map_or("", |(p, _)| p).to_string();
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).unwrap();
+
+                let mut tree = store.lock().unwrap();
+                let (status, reply) = match request.method().as_str() {
+                    _ if !authorized => (401, String::new()),
+                    "MKCOL" if tree.contains_key(&path) => (405, String::new()),
+                    "MKCOL" | "PUT" | "COPY" if !tree.contains_key(&parent) => (409, String::new()),
+                    "PUT" if path.contains("broken") => (500, String::new()),
+                    "MKCOL" => {
+                        tree.insert(path, None);
+                        (201, String::new())
+                    }
+                    "PUT" => {
+                        tree.insert(path, Some((body, mtime)));
+                        (201, String::new())
+                    }
+                    "COPY" => {
+                        let file = tree[&path].clone();
+                        tree.insert(to_path(&destination.unwrap()), file);
+                        (201, String::new())
+                    }
+                    "PROPFIND" if tree.contains_key(&path) => {
+                        let responses: String = tree
+                            .iter()
+                            .filter(|(p, _)| p.rsplit_once('/').map_or("", |(d, _)| d) == path && !p.is_empty())
+                            .map(|(p, entry)| {
+                                let (kind, len) = match entry {
+                                    None => ("<d:collection/>", String::new()),
+                                    Some((data, _)) => (
+                                        "",
+                                        format!(
+                                            "<d:getcontentlength>{}</d:getcontentlength><oc:checksums>\
+                                             <oc:checksum>SHA1:0 MD5:{} ADLER32:0</oc:checksum></oc:checksums>",
+                                            data.len(),
+                                            hex::encode(md5::Md5::digest(data))
+                                        ),
+                                    ),
+                                };
+                                format!(
+                                    "<d:response><d:href>/dav/files/me/Photos/{}</d:href><d:propstat><d:prop>\
+                                     <d:resourcetype>{}</d:resourcetype>{}</d:prop></d:propstat></d:response>",
+                                    uri_encode(p, false),
+                                    kind,
+                                    len
+                                )
+                            })
+                            .collect();
+                        (
+                            207,
+                            format!(
+                                "<d:multistatus xmlns:d=\"DAV:\" xmlns:oc=\"http://owncloud.org/ns\">{}</d:multistatus>",
+                                responses
+                            ),
+                        )
+                    }
+                    _ => (404, String::new()),
+                };
+                drop(tree);
+                let _ = request.respond(tiny_http::Response::from_string(reply).with_status_code(status));
+            }
+        });
+        (url, tree)
+    }
+
+    #[test]
+    fn test_webdav_upload_and_resume() {
+        let (url, tree) = start_server();
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        zip.start_file("a b.jpg", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"small").unwrap();
+        zip.finish().unwrap();
+
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let date = chrono::NaiveDate::from_ymd_opt(2023, 5, 1).unwrap().and_hms_opt(12, 0, 0);
+        let mut media = Media::new(zip_paths[0].clone(), 0, 0, "a b.jpg".into(), 5);
+        media.date = date;
+        media.albums = vec!["Trip".into()];
+        let media = [media];
+
+        let target = WebDavTarget {
+            url,
+            user: Some("me".into()),
+            password: Some("pw".into()),
+        };
+        let options = WriteOptions {
+            divide_to_dates: true,
+            album_dest: Some("album"),
+            album_link: true,
+            force: false,
+            durability: Durability::None,
+            min_free_bytes: 0,
+            names: NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, Path::new("")),
+            on_conflict: ConflictPolicy::Rename,
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        let callback = |_: &crate::ProgressEvent| {};
+        let progress = ThrottledProgress::new(&callback);
+
+        let mut sink = WebDavSink::new(target.clone());
+        let result = crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+        assert_eq!((result.files_written, result.files_skipped), (1, 0));
+        {
+            let tree = tree.lock().unwrap();
+            let (data, mtime) = tree["2023/05/a b.jpg"].clone().unwrap();
+            assert_eq!(data, b"small");
+            assert_eq!(mtime, unix_time(date.unwrap()).map(|t| t.to_string()));
+            assert_eq!(tree["albums/Trip/a b.jpg"].clone().unwrap().0, b"small");
+        }
+
+        // A second run finds the files with PROPFIND and uploads nothing
+        let before = tree.lock().unwrap().len();
+        let mut sink = WebDavSink::new(target);
+        let result = crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+        assert_eq!((result.files_written, result.files_skipped), (0, 1));
+        assert_eq!(tree.lock().unwrap().len(), before);
+    }
+
+    #[test]
+    fn test_webdav_compare_uses_checksums() {
+        let (url, tree) = start_server();
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        zip.start_file("a.jpg", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"small").unwrap();
+        zip.finish().unwrap();
+
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let media = [Media::new(zip_paths[0].clone(), 0, 0, "a.jpg".into(), 5)];
+        let target = WebDavTarget {
+            url,
+            user: Some("me".into()),
+            password: Some("pw".into()),
+        };
+        let options = WriteOptions {
+            divide_to_dates: false,
+            album_dest: None,
+            album_link: false,
+            force: false,
+            durability: Durability::None,
+            min_free_bytes: 0,
+            names: NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, Path::new("")),
+            on_conflict: ConflictPolicy::Compare,
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        let callback = |_: &crate::ProgressEvent| {};
+        let progress = ThrottledProgress::new(&callback);
+        let run = || {
+            let mut sink = WebDavSink::new(target.clone());
+            let result =
+                crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+            (result.files_written, result.files_skipped)
+        };
+        assert_eq!(run(), (1, 0));
+
+        // Same MD5: the uploaded file is kept
+        assert_eq!(run(), (0, 1));
+
+        // Same size, different content: uploaded again under another name
+        tree.lock().unwrap().insert("a.jpg".into(), Some((b"smell".to_vec(), None)));
+        assert_eq!(run(), (1, 0));
+        let tree = tree.lock().unwrap();
+        assert_eq!(tree["a.jpg"].clone().unwrap().0, b"smell");
+        assert_eq!(tree["a(1).jpg"].clone().unwrap().0, b"small");
+    }
+
+    #[test]
+    fn test_webdav_failed_upload_does_not_abort_run() {
+        let (url, tree) = start_server();
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for name in ["a.jpg", "broken.jpg"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let media = [
+            Media::new(zip_paths[0].clone(), 0, 0, "a.jpg".into(), 5),
+            Media::new(zip_paths[0].clone(), 0, 1, "broken.jpg".into(), 10),
+        ];
+        let mut target = WebDavTarget {
+            url,
+            user: Some("me".into()),
+            password: Some("pw".into()),
+        };
+        let options = WriteOptions {
+            divide_to_dates: false,
+            album_dest: None,
+            album_link: false,
+            force: true,
+            durability: Durability::None,
+            min_free_bytes: 0,
+            names: NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, Path::new("")),
+            on_conflict: ConflictPolicy::Rename,
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        let callback = |_: &crate::ProgressEvent| {};
+        let progress = ThrottledProgress::new(&callback);
+
+        // A server error loses only that file
+        let mut sink = WebDavSink::new(target.clone());
+        let result = crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).unwrap();
+        assert_eq!(result.files_written, 1);
+        assert_eq!(result.failed.len(), 1);
+        assert!(matches!(result.failed[0].cause, FailureCause::Network { .. }));
+        assert_eq!(tree.lock().unwrap()["a.jpg"].clone().unwrap().0, b"a.jpg");
+
+        // A refused login would fail every file, so it ends the run
+        target.password = Some("wrong".into());
+        let mut sink = WebDavSink::new(target);
+        assert!(crate::sink::write_media(&mut sink, &media, &zip_paths, &options, None, &progress, None).is_err());
+    }
+}
@@ -1,23 +1,94 @@
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicU64;
 
+use serde::{Deserialize, Serialize};
 use zip::ZipArchive;
 
+use crate::dedup::{compute_streaming_hash, HashAlgorithm};
 use crate::media::Media;
-use crate::ThrottledProgress;
+use crate::sanitize::{NameRules, RenamedName};
+use crate::{Error, SkipReason, Stage, ThrottledProgress};
+
+/// Suffix of in-progress output files; they are renamed once complete.
+pub const TEMP_SUFFIX: &str = ".gpth-tmp";
+
+/// How hard to push written files to stable storage before renaming them into place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// No fsync; a killed process never leaves a partial file under its final
+    /// name, but a power loss may
+    #[default]
+    None,
+    /// fsync each file before renaming it
+    File,
+    /// fsync each file and its directory after renaming
+    Full,
+}
+
+/// What to do when a media file's destination already exists in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing file and don't write
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Keep an existing file of the same size; otherwise write as `name(n).ext`
+    #[default]
+    Rename,
+    /// Keep the existing file only if its content hash matches; otherwise
+    /// write as `name(n).ext`
+    Compare,
+}
+
+impl ConflictPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Rename => "rename",
+            ConflictPolicy::Compare => "compare",
+        }
+    }
+}
+
+impl std::fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Temporary path used while writing `dest`, in the same directory.
+fn temp_path_for(dest: &Path) -> PathBuf {
+    let name = dest.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    dest.with_file_name(format!(".{}{}", name, TEMP_SUFFIX))
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.ends_with(TEMP_SUFFIX))
+}
 
 /// Recursively scan directory for existing files with sizes (for fast exists/size checks).
 /// Returns HashMap<path, size> to avoid repeated stat() calls.
+/// Stale temp files from an interrupted run are deleted along the way.
 fn scan_existing_files(dir: &Path) -> HashMap<PathBuf, u64> {
     let mut files = HashMap::new();
-    scan_existing_files_recursive(dir, &mut files);
+    scan_existing_files_recursive(dir, &mut Some(&mut files));
     files
 }
 
-fn scan_existing_files_recursive(dir: &Path, files: &mut HashMap<PathBuf, u64>) {
+/// Delete stale temp files without collecting anything else.
+fn remove_stale_temp_files(dir: &Path) {
+    scan_existing_files_recursive(dir, &mut None);
+}
+
+fn scan_existing_files_recursive(dir: &Path, files: &mut Option<&mut HashMap<PathBuf, u64>>) {
     let Ok(entries) = fs::read_dir(dir) else {
         return;
     };
@@ -25,70 +96,452 @@ fn scan_existing_files_recursive(dir: &Path, files: &mut HashMap<PathBuf, u64>)
         let path = entry.path();
         if path.is_dir() {
             scan_existing_files_recursive(&path, files);
-        } else if let Ok(meta) = entry.metadata() {
-            files.insert(path, meta.len());
+        } else if is_temp_file(&path) {
+            let _ = fs::remove_file(&path);
+        } else if let Some(files) = files {
+            if let Ok(meta) = entry.metadata() {
+                files.insert(path, meta.len());
+            }
         }
     }
 }
 
-/// Assign output paths, then write files.
-/// Result of the write phase.
-pub struct WriteResult {
-    pub assignments: Vec<PathBuf>,
-    pub files_skipped: u64,
+/// fsync a directory so a rename inside it is durable (no-op where unsupported).
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
 }
 
-pub fn write_output(
+/// Why a media file could not be written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FailureCause {
+    /// The extracted bytes did not match the ZIP entry's CRC32
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The ZIP (or this entry in it) could not be read
+    CorruptEntry { message: String },
+    /// The output path is too long for the target filesystem
+    PathTooLong { message: String },
+    /// The output location is not writable
+    PermissionDenied { message: String },
+    /// Any other I/O error
+    Io { message: String },
+    /// A remote target (S3, WebDAV) did not take the file
+    Network { message: String },
+}
+
+impl FailureCause {
+    /// Classify an I/O error from creating, writing or renaming an output file.
+    pub fn from_io(e: &io::Error) -> Self {
+        let message = e.to_string();
+        if e.kind() == io::ErrorKind::PermissionDenied {
+            FailureCause::PermissionDenied { message }
+        } else if is_name_too_long(e) {
+            FailureCause::PathTooLong { message }
+        } else {
+            FailureCause::Io { message }
+        }
+    }
+
+    /// Classify an error from opening a ZIP or one of its entries.
+    pub fn from_zip(e: &zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(io_err) => FailureCause::from_io(io_err),
+            other => FailureCause::CorruptEntry {
+                message: other.to_string(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for FailureCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureCause::ChecksumMismatch { expected, actual } => {
+                write!(f, "CRC32 mismatch (expected {:08x}, got {:08x})", expected, actual)
+            }
+            FailureCause::CorruptEntry { message } => write!(f, "unreadable zip entry: {}", message),
+            FailureCause::PathTooLong { message } => write!(f, "path too long: {}", message),
+            FailureCause::PermissionDenied { message } => write!(f, "permission denied: {}", message),
+            FailureCause::Io { message } => write!(f, "I/O error: {}", message),
+            FailureCause::Network { message } => write!(f, "upload failed: {}", message),
+        }
+    }
+}
+
+/// ENAMETOOLONG / ERROR_FILENAME_EXCED_RANGE
+fn is_name_too_long(e: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    const NAME_TOO_LONG: i32 = 36;
+    #[cfg(all(unix, not(target_os = "linux")))]
+    const NAME_TOO_LONG: i32 = 63;
+    #[cfg(windows)]
+    const NAME_TOO_LONG: i32 = 206;
+    #[cfg(not(any(unix, windows)))]
+    const NAME_TOO_LONG: i32 = -1;
+    e.raw_os_error() == Some(NAME_TOO_LONG)
+}
+
+/// A media file that could not be written; the rest of the run continues.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedItem {
+    /// Source zip file
+    pub zip_file: String,
+    /// Relative path inside the zip
+    pub zip_path: String,
+    /// Output path the file was assigned
+    pub output_path: PathBuf,
+    pub cause: FailureCause,
+}
+
+impl FailedItem {
+    pub(crate) fn new(zip_paths: &[String], m: &Media, output_path: &Path, cause: FailureCause) -> Self {
+        Self {
+            zip_file: zip_paths[m.zip_index].clone(),
+            zip_path: m.zip_path.clone(),
+            output_path: output_path.to_path_buf(),
+            cause,
+        }
+    }
+}
+
+/// Writer adapter that computes the CRC32 of everything written through it.
+struct CrcWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    /// Bytes written so far
+    len: u64,
+}
+
+impl<W: Write> CrcWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            len: 0,
+        }
+    }
+
+    fn crc32(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Stream a ZIP entry into `dest`, checking the bytes written against the
+/// entry's CRC32. Returns why the entry is bad, if it is: a stream that
+/// cannot be decoded to the end, or one that decodes to the wrong CRC32.
+/// The caller removes the file.
+fn extract_verified(
+    entry: &mut zip::read::ZipFile,
+    dest: &Path,
+    durability: Durability,
+) -> io::Result<Option<FailureCause>> {
+    let expected = entry.crc32();
+    let mut out_file = CrcWriter::new(io::BufWriter::new(File::create(dest)?));
+    let invalid = match io::copy(entry, &mut out_file) {
+        Ok(_) => None,
+        // Corrupt data, or the zip reader's own checksum check after the last byte
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Some(e),
+        Err(e) => return Err(e),
+    };
+    out_file.flush()?;
+
+    let actual = out_file.crc32();
+    match invalid {
+        Some(e) if out_file.len != entry.size() => {
+            return Ok(Some(FailureCause::CorruptEntry { message: e.to_string() }));
+        }
+        _ if actual != expected => return Ok(Some(FailureCause::ChecksumMismatch { expected, actual })),
+        Some(e) => return Ok(Some(FailureCause::CorruptEntry { message: e.to_string() })),
+        None => {}
+    }
+    if durability != Durability::None {
+        out_file.inner.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    Ok(None)
+}
+
+/// Write a ZIP entry to a temp file next to `dest`, then rename it into place.
+/// A failed or interrupted write never leaves a partial file under `dest`.
+fn write_entry_atomic(
+    entry: &mut zip::read::ZipFile,
+    dest: &Path,
+    mtime: Option<filetime::FileTime>,
+    durability: Durability,
+) -> io::Result<Option<FailureCause>> {
+    let temp = temp_path_for(dest);
+    match extract_verified(entry, &temp, durability) {
+        Ok(None) => {}
+        other => {
+            let _ = fs::remove_file(&temp);
+            return other;
+        }
+    }
+    if let Some(ft) = mtime {
+        filetime::set_file_mtime(&temp, ft).ok();
+    }
+    if let Err(e) = fs::rename(&temp, dest) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    if durability == Durability::Full {
+        if let Some(parent) = dest.parent() {
+            sync_dir(parent);
+        }
+    }
+    Ok(None)
+}
+
+/// Copy a file through a temp file and rename, like `write_entry_atomic`.
+fn copy_atomic(src: &Path, dest: &Path, durability: Durability) -> io::Result<()> {
+    let temp = temp_path_for(dest);
+    let result = fs::copy(src, &temp).and_then(|_| {
+        if durability != Durability::None {
+            File::open(&temp)?.sync_all()?;
+        }
+        fs::rename(&temp, dest)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    if durability == Durability::Full {
+        if let Some(parent) = dest.parent() {
+            sync_dir(parent);
+        }
+    }
+    Ok(())
+}
+
+/// Extract one media file to `dest`, returning the cause instead of aborting on failure.
+fn write_media(
+    archive: &mut ZipArchive<File>,
+    m: &Media,
+    dest: &Path,
+    durability: Durability,
+) -> Result<(), FailureCause> {
+    let mtime = m.date.and_then(|dt| {
+        let local = dt.and_local_timezone(chrono::Local).single()?;
+        Some(filetime::FileTime::from_unix_time(local.timestamp(), 0))
+    });
+    let mut entry = archive
+        .by_index(m.entry_index)
+        .map_err(|e| FailureCause::from_zip(&e))?;
+    match write_entry_atomic(&mut entry, dest, mtime, durability) {
+        Ok(None) => Ok(()),
+        Ok(Some(cause)) => Err(cause),
+        Err(e) => Err(FailureCause::from_io(&e)),
+    }
+}
+
+/// A file already present at the output target.
+#[derive(Debug, Clone)]
+pub(crate) struct ExistingFile {
+    /// Path as found in the output, which may differ in case or Unicode form from the planned one
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    /// Listed by a remote sink rather than found on local disk
+    pub(crate) remote: bool,
+    /// ETag of a remote object; local files are hashed directly instead
+    pub(crate) etag: Option<String>,
+    /// MD5 of a remote file, for servers that report checksums
+    pub(crate) md5: Option<String>,
+}
+
+impl ExistingFile {
+    fn local(path: PathBuf, size: u64) -> Self {
+        Self {
+            path,
+            size,
+            remote: false,
+            etag: None,
+            md5: None,
+        }
+    }
+}
+
+/// Outcome of a conflict with a file already in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Conflict {
+    /// The existing file stands in for the media file; nothing is written
+    Keep,
+    /// Write over the existing file
+    Replace,
+    /// Leave the existing file alone and pick another name
+    Avoid,
+}
+
+/// Compares existing output files with ZIP entries by content hash,
+/// opening each ZIP at most once.
+pub(crate) struct ContentComparer<'a> {
+    zip_paths: &'a [String],
+    algorithm: HashAlgorithm,
+    archives: HashMap<usize, Option<ZipArchive<File>>>,
+}
+
+impl<'a> ContentComparer<'a> {
+    pub(crate) fn new(zip_paths: &'a [String], algorithm: HashAlgorithm) -> Self {
+        Self {
+            zip_paths,
+            algorithm,
+            archives: HashMap::new(),
+        }
+    }
+
+    /// Run `f` over the media file's ZIP entry; None if it cannot be read.
+    fn with_entry<T>(&mut self, m: &Media, f: impl FnOnce(zip::read::ZipFile<'_>) -> io::Result<T>) -> Option<T> {
+        let zip_path = &self.zip_paths[m.zip_index];
+        let archive = self
+            .archives
+            .entry(m.zip_index)
+            .or_insert_with(|| File::open(zip_path).ok().and_then(|f| ZipArchive::new(f).ok()));
+        archive.as_mut()?.by_index(m.entry_index).ok().and_then(|entry| f(entry).ok())
+    }
+
+    /// True if `existing` has the same content as the media file. Any read error
+    /// counts as different, so the existing file is never trusted blindly.
+    pub(crate) fn same_content(&mut self, m: &Media, existing: &ExistingFile) -> bool {
+        if existing.size != m.size {
+            return false;
+        }
+        match (&existing.etag, &existing.md5) {
+            (Some(etag), _) => return self.matches_etag(m, etag),
+            (None, Some(md5)) => return self.matches_md5(m, md5),
+            // Remote content without a comparable tag can't be checked
+            (None, None) if existing.remote => return false,
+            (None, None) => {}
+        }
+        let Ok(actual) = File::open(&existing.path).and_then(|f| compute_streaming_hash(f, self.algorithm)) else {
+            return false;
+        };
+        // Dedup already hashed files that shared a size; reuse that
+        if let Some(expected) = &m.hash {
+            return *expected == actual;
+        }
+        let algorithm = self.algorithm;
+        self.with_entry(m, |entry| compute_streaming_hash(entry, algorithm))
+            .is_some_and(|expected| expected == actual)
+    }
+
+    /// Remote objects can't be hashed in place; compare the ETag the server
+    /// would have given this content instead.
+    #[cfg(feature = "s3")]
+    fn matches_etag(&mut self, m: &Media, etag: &str) -> bool {
+        let size = m.size;
+        self.with_entry(m, |entry| crate::s3::content_etag(entry, size))
+            .is_some_and(|expected| expected == etag)
+    }
+
+    #[cfg(not(feature = "s3"))]
+    fn matches_etag(&mut self, _m: &Media, _etag: &str) -> bool {
+        false
+    }
+
+    /// Compare the MD5 a WebDAV server reports for an existing file.
+    #[cfg(any(feature = "s3", feature = "webdav"))]
+    fn matches_md5(&mut self, m: &Media, md5: &str) -> bool {
+        use md5::{Digest, Md5};
+        self.with_entry(m, |mut entry| {
+            let mut hasher = Md5::new();
+            io::copy(&mut entry, &mut hasher)?;
+            Ok(hex::encode(hasher.finalize()))
+        })
+        .is_some_and(|expected| expected.eq_ignore_ascii_case(md5))
+    }
+
+    #[cfg(not(any(feature = "s3", feature = "webdav")))]
+    fn matches_md5(&mut self, _m: &Media, _md5: &str) -> bool {
+        false
+    }
+
+    /// Decide what to do about an existing file at the media file's destination.
+    pub(crate) fn resolve(&mut self, policy: ConflictPolicy, m: &Media, existing: &ExistingFile) -> Conflict {
+        match policy {
+            ConflictPolicy::Skip => Conflict::Keep,
+            ConflictPolicy::Overwrite => Conflict::Replace,
+            ConflictPolicy::Rename if existing.size == m.size => Conflict::Keep,
+            ConflictPolicy::Compare if self.same_content(m, existing) => Conflict::Keep,
+            ConflictPolicy::Rename | ConflictPolicy::Compare => Conflict::Avoid,
+        }
+    }
+}
+
+/// Settings for the write phase.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions<'a> {
+    pub divide_to_dates: bool,
+    /// `Some("album")` to also write album folders
+    pub album_dest: Option<&'a str>,
+    pub album_link: bool,
+    /// Overwrite everything without looking at existing files
+    pub force: bool,
+    pub durability: Durability,
+    pub min_free_bytes: u64,
+    pub names: NameRules,
+    pub on_conflict: ConflictPolicy,
+    /// Used by `ConflictPolicy::Compare`
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// Destination paths chosen for each media file.
+pub(crate) struct PathPlan {
+    pub(crate) assignments: Vec<PathBuf>,
+    /// Media already present in the output (checkpoint or conflict policy)
+    pub(crate) skip_indices: HashSet<usize>,
+    pub(crate) renamed: Vec<RenamedName>,
+    pub(crate) failed: Vec<(usize, FailedItem)>,
+}
+
+/// Assign each media file a destination under `output_dir`, resolving name
+/// collisions among this run's files and with `existing_files`
+/// (keyed by path key). Date directories are created when
+/// `create_dirs` is set.
+pub(crate) fn plan_paths(
     media: &[Media],
     zip_paths: &[String],
     output_dir: &Path,
-    divide_to_dates: bool,
-    album_dest: Option<&str>,
-    album_link: bool,
-    force: bool,
-    progress: &ThrottledProgress,
-    checkpoint_saver: Option<&mut crate::checkpoint::CheckpointSaver>,
-    cancel_token: Option<&crate::checkpoint::CancellationToken>,
-) -> anyhow::Result<WriteResult> {
-    fs::create_dir_all(output_dir)?;
-
-    // Get already written files from checkpoint (if resuming)
-    // Map: zip_path -> output_path
-    let already_written: HashMap<String, PathBuf> = checkpoint_saver
-        .as_ref()
-        .map(|s| s.get_written_map())
-        .unwrap_or_default();
-
-    // Phase 1: Assign destination paths (sequential - needs collision tracking)
-    // Use counters per base path to avoid O(n²) worst case
+    options: &WriteOptions,
+    already_written: &HashMap<String, PathBuf>,
+    existing_files: &HashMap<PathBuf, ExistingFile>,
+    create_dirs: bool,
+) -> PathPlan {
+    // Sequential - needs collision tracking. Use counters per base path to avoid O(n²) worst case.
+    // Collision sets are keyed by `names.path_key`, so on case-insensitive
+    // targets IMG_1.JPG and img_1.jpg are treated as the same path.
     let mut name_counters: HashMap<PathBuf, u32> = HashMap::new();
     let mut used_paths: HashSet<PathBuf> = HashSet::new();
     let mut created_dirs: HashSet<PathBuf> = HashSet::new();
     let mut assignments: Vec<PathBuf> = Vec::with_capacity(media.len());
 
     let mut skip_indices: HashSet<usize> = HashSet::new();
+    let mut comparer = ContentComparer::new(zip_paths, options.hash_algorithm);
+    // Names changed to be valid on the target filesystem
+    let mut renamed: Vec<RenamedName> = Vec::new();
+    // Files that failed; the run continues without them
+    let mut failed: Vec<(usize, FailedItem)> = Vec::new();
 
     // Pre-populate used_paths with checkpoint files (fast, no I/O)
     for path in already_written.values() {
-        used_paths.insert(path.clone());
+        used_paths.insert(options.names.path_key(path));
     }
 
-    // Pre-scan existing files in output directory to avoid repeated exists()/stat() calls
-    // Skip scanning if:
-    // - force mode (overwrite all)
-    // - checkpoint has written files (they're already tracked)
-    let existing_files: HashMap<PathBuf, u64> = if force {
-        // Force mode - skip all existence checks, overwrite everything
-        HashMap::new()
-    } else if !already_written.is_empty() {
-        // Resuming from checkpoint - skip scan, checkpoint tracks all written files
-        HashMap::new()
-    } else if output_dir.exists() {
-        scan_existing_files(output_dir)
-    } else {
-        HashMap::new()
-    };
-
     for (idx, m) in media.iter().enumerate() {
         // Fast path: if file was already written (from checkpoint), use saved path
         if let Some(saved_path) = already_written.get(&m.zip_path) {
@@ -98,7 +551,7 @@ pub fn write_output(
         }
 
         // Compute destination directory
-        let sub_dir = if divide_to_dates {
+        let sub_dir = if options.divide_to_dates {
             match &m.date {
                 Some(dt) => {
                     let year = dt.format("%Y").to_string();
@@ -111,76 +564,289 @@ pub fn write_output(
             output_dir.to_path_buf()
         };
 
+        let filename = options.names.sanitize(&m.filename);
+        // A change of Unicode form alone is not reported as a rename
+        if filename != options.names.normalize(&m.filename) {
+            renamed.push(RenamedName::file(&m.filename, &filename));
+        }
+        let base_dest = sub_dir.join(&filename);
+
         // Create directory only once per unique path
-        if !created_dirs.contains(&sub_dir) {
-            fs::create_dir_all(&sub_dir)?;
+        if create_dirs && !created_dirs.contains(&sub_dir) {
+            if let Err(e) = fs::create_dir_all(&sub_dir) {
+                failed.push((idx, FailedItem::new(zip_paths, m, &base_dest, FailureCause::from_io(&e))));
+                assignments.push(base_dest);
+                continue;
+            }
             created_dirs.insert(sub_dir.clone());
         }
 
-        let base_dest = sub_dir.join(&m.filename);
-        let counter = name_counters.entry(base_dest.clone()).or_insert(0);
+        let base_key = options.names.path_key(&base_dest);
+        let counter = name_counters.entry(base_key.clone()).or_insert(0);
 
-        let can_use_base = *counter == 0 && !used_paths.contains(&base_dest);
-        
-        // Check existing file using pre-scanned cache (O(1), no I/O)
-        let existing_size = existing_files.get(&base_dest).copied();
-        let existing_is_same = can_use_base && existing_size == Some(m.size);
+        let can_use_base = *counter == 0 && !used_paths.contains(&base_key);
 
-        // Skip if existing file has same size (already written in previous run)
-        if existing_is_same {
-            skip_indices.insert(idx);
-        }
+        // Check existing file using pre-scanned cache (O(1), no I/O)
+        let existing = existing_files.get(&base_key);
+        let conflict = match existing {
+            Some(existing) if can_use_base => Some(comparer.resolve(options.on_conflict, m, existing)),
+            _ => None,
+        };
 
-        let dest = if existing_is_same || (can_use_base && existing_size.is_none()) {
-            base_dest
-        } else {
-            let stem = Path::new(&m.filename)
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("file");
-            let ext = Path::new(&m.filename)
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("");
-
-            // Start from the current counter value (avoid re-checking already used numbers)
-            loop {
-                *counter += 1;
-                let new_name = if ext.is_empty() {
-                    format!("{}({})", stem, counter)
-                } else {
-                    format!("{}({}).{}", stem, counter, ext)
-                };
-                let candidate = sub_dir.join(&new_name);
-                // Use cache for existence check (O(1), no I/O)
-                let candidate_exists = existing_files.contains_key(&candidate);
-                if !used_paths.contains(&candidate) && !candidate_exists {
-                    break candidate;
+        let (dest, dest_key) = match (existing, conflict) {
+            // Keep the existing file's spelling on case-insensitive targets
+            (Some(existing), Some(Conflict::Keep)) => {
+                // The existing file already holds this media (e.g. a previous run)
+                skip_indices.insert(idx);
+                (existing.path.clone(), base_key)
+            }
+            (Some(existing), Some(Conflict::Replace)) => (existing.path.clone(), base_key),
+            (None, _) if can_use_base => (base_dest, base_key),
+            _ => {
+                // Start from the current counter value (avoid re-checking already used numbers)
+                loop {
+                    *counter += 1;
+                    let candidate = sub_dir.join(options.names.numbered(&filename, *counter));
+                    let candidate_key = options.names.path_key(&candidate);
+                    if used_paths.contains(&candidate_key) {
+                        continue;
+                    }
+                    // Use cache for existence check (O(1), no I/O)
+                    match existing_files.get(&candidate_key) {
+                        None => break (candidate, candidate_key),
+                        Some(existing) if options.on_conflict == ConflictPolicy::Overwrite => {
+                            break (existing.path.clone(), candidate_key)
+                        }
+                        // A renamed copy from a previous run with the same content
+                        Some(existing)
+                            if options.on_conflict == ConflictPolicy::Compare && comparer.same_content(m, existing) =>
+                        {
+                            skip_indices.insert(idx);
+                            break (existing.path.clone(), candidate_key);
+                        }
+                        Some(_) => {}
+                    }
                 }
             }
         };
 
-        used_paths.insert(dest.clone());
+        used_paths.insert(dest_key);
         assignments.push(dest);
     }
 
-    // Phase 2: Write files in parallel (skip unchanged files and checkpoint files)
-    let work_count = media.len() - skip_indices.len();
-    let total = work_count as u64;
-    let write_counter = AtomicU64::new(0);
+    PathPlan {
+        assignments,
+        skip_indices,
+        renamed,
+        failed,
+    }
+}
 
-    let num_threads = rayon::current_num_threads();
+/// Choose the path of `m`'s entry in `album_dir`. Names taken earlier in
+/// this run (`used`, by path key) get a counter, and a file already at the
+/// path (`existing`, given the path and its key) is handled by the conflict
+/// policy with the same rules as `plan_paths` uses for primary files.
+/// Returns the path and whether an existing file stands in for the entry.
+pub(crate) fn plan_album_entry(
+    m: &Media,
+    album_dir: &Path,
+    options: &WriteOptions,
+    used: &mut HashSet<PathBuf>,
+    comparer: &mut ContentComparer,
+    existing: impl Fn(&Path, &Path) -> Option<ExistingFile>,
+) -> (PathBuf, bool) {
+    let names = options.names;
+    let filename = names.sanitize(&m.filename);
+    let mut entry = album_dir.join(&filename);
+    let mut counter = 0;
+    loop {
+        let key = names.path_key(&entry);
+        if used.insert(key.clone()) {
+            let Some(found) = existing(&entry, &key) else {
+                return (entry, false);
+            };
+            let conflict = match options.on_conflict {
+                _ if counter == 0 => comparer.resolve(options.on_conflict, m, &found),
+                ConflictPolicy::Overwrite => Conflict::Replace,
+                // A numbered copy from an earlier run with the same content
+                ConflictPolicy::Compare if comparer.same_content(m, &found) => Conflict::Keep,
+                _ => Conflict::Avoid,
+            };
+            match conflict {
+                Conflict::Keep => return (found.path, true),
+                Conflict::Replace => return (found.path, false),
+                Conflict::Avoid => {}
+            }
+        }
+        counter += 1;
+        entry = album_dir.join(names.numbered(&filename, counter));
+    }
+}
+
+/// Assign output paths, then write files.
+/// Result of the write phase.
+pub struct WriteResult {
+    pub assignments: Vec<PathBuf>,
+    pub files_written: u64,
+    pub files_skipped: u64,
+    /// Files moved to new paths instead of being written again (`--migrate`)
+    pub files_moved: u64,
+    /// File and album names rewritten for the target filesystem
+    pub renamed: Vec<RenamedName>,
+    /// Files created under `<output>/albums/` (copies or symlinks)
+    pub album_files: Vec<PathBuf>,
+    /// Files that could not be written
+    pub failed: Vec<FailedItem>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn write_output(
+    media: &[Media],
+    zip_paths: &[String],
+    output_dir: &Path,
+    options: &WriteOptions,
+    imported: &HashMap<String, PathBuf>,
+    progress: &ThrottledProgress,
+    mut checkpoint_saver: Option<&mut crate::checkpoint::CheckpointSaver>,
+    cancel_token: Option<&crate::checkpoint::CancellationToken>,
+) -> crate::Result<WriteResult> {
+    let WriteOptions {
+        album_dest,
+        album_link,
+        force,
+        durability,
+        min_free_bytes,
+        names,
+        ..
+    } = *options;
+    fs::create_dir_all(output_dir).map_err(Error::io(output_dir))?;
+
+    // Files written under earlier path options (--migrate), to be moved to
+    // their new paths. Map: zip_path -> old output path
+    let migration: HashMap<String, PathBuf> = checkpoint_saver
+        .as_deref_mut()
+        .and_then(|s| s.take_migration())
+        .unwrap_or_default();
+
+    // Get already written files from checkpoint (if resuming)
+    // Map: zip_path -> output_path
+    let already_written: HashMap<String, PathBuf> = checkpoint_saver
+        .as_ref()
+        .map(|s| s.get_written_map())
+        .unwrap_or_default();
+
+    // Pre-scan existing files in output directory to avoid repeated exists()/stat() calls
+    // Skip scanning if:
+    // - force mode (overwrite all)
+    // - checkpoint has written files (they're already tracked)
+    // Map: path key -> existing file
+    let mut existing_files: HashMap<PathBuf, ExistingFile> = if force {
+        // Force mode - skip all existence checks, overwrite everything
+        remove_stale_temp_files(output_dir);
+        HashMap::new()
+    } else if !already_written.is_empty() {
+        // Resuming from checkpoint - skip scan, checkpoint tracks all written files
+        remove_stale_temp_files(output_dir);
+        HashMap::new()
+    } else if output_dir.exists() {
+        scan_existing_files(output_dir)
+            .into_iter()
+            .map(|(path, size)| (names.path_key(&path), ExistingFile::local(path, size)))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    // Checkpointed files that changed since are rewritten in place, whatever the conflict policy
+    if let Some(saver) = &checkpoint_saver {
+        for path in saver.requeued() {
+            existing_files.remove(&names.path_key(path));
+        }
+    }
+
+    // Files about to move are not in the way of the new plan
+    for path in migration.values() {
+        existing_files.remove(&names.path_key(path));
+    }
+
+    // Media imported by an earlier run stay where they are, like checkpoint files
+    let mut known = already_written.clone();
+    known.extend(imported.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    // Phase 1: Assign destination paths (sequential - needs collision tracking)
+    let PathPlan {
+        assignments,
+        mut skip_indices,
+        mut renamed,
+        failed,
+    } = plan_paths(media, zip_paths, output_dir, options, &known, &existing_files, true);
+    let assign_failed: HashSet<usize> = failed.iter().map(|(i, _)| *i).collect();
+    let files_skipped = skip_indices.len() as u64;
+    for &i in &skip_indices {
+        let zip_path = &media[i].zip_path;
+        let reason = if already_written.contains_key(zip_path) {
+            SkipReason::Checkpoint
+        } else if imported.contains_key(zip_path) {
+            SkipReason::AlreadyImported
+        } else {
+            SkipReason::Exists
+        };
+        progress.file_skipped(zip_path, assignments[i].clone(), reason);
+    }
+
+    // Move migrated files instead of writing them again; any that cannot
+    // be moved are written from the ZIP
+    let moves: Vec<(usize, PathBuf, PathBuf)> = media
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !skip_indices.contains(i) && !assign_failed.contains(i))
+        .filter_map(|(i, m)| migration.get(&m.zip_path).map(|old| (i, old.clone(), assignments[i].clone())))
+        .collect();
+    let mut files_moved = 0;
+    for ((i, _, _), moved) in moves.iter().zip(move_files(&moves)) {
+        if moved {
+            skip_indices.insert(*i);
+            files_moved += 1;
+        }
+    }
+
+    // Phase 2: Write files in parallel (skip unchanged files and checkpoint files)
     let work: Vec<(usize, &Media, &PathBuf)> = media
         .iter()
         .zip(assignments.iter())
         .enumerate()
-        .filter(|(i, _)| !skip_indices.contains(i))
+        .filter(|(i, _)| !skip_indices.contains(i) && !assign_failed.contains(i))
         .map(|(i, (m, d))| (i, m, d))
         .collect();
+    let total = work.len() as u64;
+    let write_counter = AtomicU64::new(0);
+    let total_bytes: u64 = work.iter().map(|(_, m, _)| m.size).sum();
+    let bytes_counter = AtomicU64::new(0);
+
+    // Fail before writing anything if the planned output cannot fit
+    let mut required_bytes = total_bytes;
+    if album_dest == Some("album") && !album_link {
+        required_bytes += work.iter().map(|(_, m, _)| m.size * m.albums.len() as u64).sum::<u64>();
+    }
+    crate::disk_space::ensure_space(output_dir, required_bytes, min_free_bytes)?;
+    progress.start(Stage::Write, total);
 
-    // For checkpoint tracking, we need thread-safe collection of written files
+    let num_threads = rayon::current_num_threads();
+
+    // Each written file goes to the checkpoint journal as soon as it is done,
+    // so a crash loses at most the unflushed tail. Files found unchanged in
+    // the output count as written too, so a re-run after failures only
+    // retries the failed ones.
     use std::sync::Mutex;
-    let written_files: Mutex<Vec<(String, PathBuf, u64)>> = Mutex::new(Vec::new());
+    let checkpoint_saver = checkpoint_saver.map(|saver| {
+        for &i in &skip_indices {
+            if !already_written.contains_key(&media[i].zip_path) {
+                saver.mark_written(&media[i].zip_path, &assignments[i], media[i].size);
+            }
+        }
+        Mutex::new(saver)
+    });
+    let failed: Mutex<Vec<(usize, FailedItem)>> = Mutex::new(failed);
     let cancelled = std::sync::atomic::AtomicBool::new(false);
 
     let mut by_zip: HashMap<usize, Vec<(usize, &Media, &PathBuf)>> = HashMap::new();
@@ -197,153 +863,642 @@ pub fn write_output(
             }
         }
 
-        let chunk_size = (entries.len() + num_threads - 1) / num_threads;
+        let chunk_size = entries.len().div_ceil(num_threads);
         let chunks: Vec<&[(usize, &Media, &PathBuf)]> = entries.chunks(chunk_size).collect();
         let zip_path = &zip_paths[*zip_idx];
 
-        std::thread::scope(|s| -> anyhow::Result<()> {
+        std::thread::scope(|s| {
             let handles: Vec<_> = chunks
                 .into_iter()
                 .map(|chunk| {
                     let write_counter = &write_counter;
-                    let zip_path = zip_path;
+                    let bytes_counter = &bytes_counter;
                     let progress = &progress;
-                    let written_files = &written_files;
-                    let cancel_token = cancel_token;
+                    let checkpoint_saver = &checkpoint_saver;
+                    let failed = &failed;
                     let cancelled = &cancelled;
-                    s.spawn(move || -> anyhow::Result<()> {
-                        let file = File::open(zip_path)?;
-                        let mut archive = ZipArchive::new(file)?;
+                    s.spawn(move || {
+                        let archive = File::open(zip_path)
+                            .map_err(|e| FailureCause::from_io(&e))
+                            .and_then(|f| ZipArchive::new(f).map_err(|e| FailureCause::from_zip(&e)));
+                        let mut archive = match archive {
+                            Ok(a) => a,
+                            Err(cause) => {
+                                // Every file in this chunk depends on the unreadable zip
+                                let mut failed = failed.lock().unwrap();
+                                for &(i, m, dest) in chunk {
+                                    failed.push((i, FailedItem::new(zip_paths, m, dest, cause.clone())));
+                                }
+                                return;
+                            }
+                        };
 
-                        for &(_i, m, dest) in chunk {
+                        for &(i, m, dest) in chunk {
                             // Check for cancellation
                             if let Some(token) = cancel_token {
                                 if token.check().is_err() {
                                     cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
-                                    return Ok(());
+                                    return;
                                 }
                             }
 
-                            let mut entry = archive.by_index(m.entry_index)?;
-                            let mut out_file = io::BufWriter::new(File::create(dest)?);
-                            io::copy(&mut entry, &mut out_file)?;
+                            // Pause instead of hitting ENOSPC when free space runs low
+                            if crate::disk_space::wait_for_space(output_dir, m.size, min_free_bytes, cancel_token, progress)
+                                .is_err()
+                            {
+                                cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
+                                return;
+                            }
 
-                            if let Some(dt) = &m.date {
-                                if let Some(local) = dt.and_local_timezone(chrono::Local).single() {
-                                    let ft = filetime::FileTime::from_unix_time(local.timestamp(), 0);
-                                    filetime::set_file_mtime(dest, ft).ok();
+                            match write_media(&mut archive, m, dest, durability) {
+                                Ok(()) => {
+                                    if let Some(saver) = checkpoint_saver {
+                                        let mut saver = saver.lock().unwrap();
+                                        saver.mark_written(&m.zip_path, dest, m.size);
+                                        saver.report_saved(progress);
+                                    }
                                 }
+                                Err(cause) => failed
+                                    .lock()
+                                    .unwrap()
+                                    .push((i, FailedItem::new(zip_paths, m, dest, cause))),
                             }
 
-                            // Track written file for checkpoint
-                            written_files.lock().unwrap().push((
-                                m.zip_path.clone(),
-                                dest.clone(),
-                                m.size,
-                            ));
-
-                            let current = write_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                            progress.report("write", current, total, "Writing files");
+                            let current = write_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                            let bytes = bytes_counter.fetch_add(m.size, std::sync::atomic::Ordering::Relaxed) + m.size;
+                            progress.report_bytes(Stage::Write, current, total, (bytes, total_bytes), "Writing files");
                         }
-                        Ok(())
                     })
                 })
                 .collect();
 
             for h in handles {
-                h.join().unwrap()?;
+                h.join().unwrap();
             }
-            Ok(())
-        })?;
+        });
     }
 
-    // Update checkpoint with written files
-    if let Some(saver) = checkpoint_saver {
-        let files = written_files.into_inner().unwrap();
-        for (zip_path, output_path, size) in files {
-            saver.mark_written(&zip_path, &output_path, size);
-        }
-        // Force save if cancelled
-        if cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+    // Force save if cancelled
+    if cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+        if let Some(saver) = checkpoint_saver {
+            let saver = saver.into_inner().unwrap();
             saver.force_save();
-            return Err(crate::checkpoint::CancelledError.into());
+            saver.report_saved(progress);
         }
+        return Err(Error::Cancelled);
     }
+    progress.finish(Stage::Write);
+
+    let (failed_indices, mut failed): (HashSet<usize>, Vec<FailedItem>) =
+        failed.into_inner().unwrap().into_iter().unzip();
+    let files_written = (media.len() - skip_indices.len() - failed_indices.len()) as u64;
 
     // Phase 3: Album output (if --album-dest album)
-    if album_dest == Some("album") {
-        write_album_folders(media, &assignments, output_dir, album_link)?;
-    }
+    let album_files = if album_dest == Some("album") {
+        let (album_files, album_failed) = write_album_folders(
+            media,
+            zip_paths,
+            &assignments,
+            &failed_indices,
+            output_dir,
+            options,
+            &mut renamed,
+        );
+        failed.extend(album_failed);
+        album_files
+    } else {
+        Vec::new()
+    };
 
     Ok(WriteResult {
         assignments,
-        files_skipped: skip_indices.len() as u64,
+        files_written,
+        files_skipped,
+        files_moved,
+        album_files,
+        failed,
+        renamed,
     })
 }
 
+/// Move files from their old to their new path, given as (media index,
+/// old, new). A file whose new path is still taken by another file's old
+/// path goes through its temporary name first. Returns which moves succeeded.
+fn move_files(moves: &[(usize, PathBuf, PathBuf)]) -> Vec<bool> {
+    let sources: HashSet<&Path> = moves.iter().map(|(_, old, _)| old.as_path()).collect();
+    let mut moved = vec![false; moves.len()];
+    let mut staged: Vec<(usize, PathBuf)> = Vec::new();
+    for (n, (_, old, new)) in moves.iter().enumerate() {
+        if old == new {
+            moved[n] = true;
+            continue;
+        }
+        if let Some(parent) = new.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if sources.contains(new.as_path()) {
+            let temp = temp_path_for(new);
+            if fs::rename(old, &temp).is_ok() {
+                staged.push((n, temp));
+            }
+        } else if !new.exists() {
+            moved[n] = fs::rename(old, new).is_ok();
+        }
+    }
+    // Anything left at a temporary name is cleaned up by the next run and
+    // written from the ZIP
+    for (n, temp) in staged {
+        let new = &moves[n].2;
+        moved[n] = !new.exists() && fs::rename(&temp, new).is_ok();
+    }
+    moved
+}
+
+/// Map each album name to a folder name valid under `names`.
+/// Album names that only differ in Unicode form share one folder. Other
+/// distinct albums never share a folder: names that are already valid keep
+/// their folder, and rewritten or case-colliding names get a counter.
+pub(crate) fn assign_album_folders<'a>(
+    media: &'a [Media],
+    names: NameRules,
+    renamed: &mut Vec<RenamedName>,
+) -> HashMap<&'a str, String> {
+    let albums: std::collections::BTreeSet<&str> =
+        media.iter().flat_map(|m| m.albums.iter().map(String::as_str)).collect();
+    let normalized: std::collections::BTreeSet<String> =
+        albums.iter().map(|n| names.normalize(n)).collect();
+    let mut folder_of: HashMap<&str, String> = HashMap::new();
+    let mut used: HashSet<String> = HashSet::new();
+
+    let (valid, invalid): (Vec<&str>, Vec<&str>) = normalized
+        .iter()
+        .map(String::as_str)
+        .partition(|n| names.sanitize(n) == *n);
+    let mut needs_folder = Vec::new();
+    for name in valid {
+        if used.insert(names.key(name)) {
+            folder_of.insert(name, name.to_string());
+        } else {
+            needs_folder.push(name);
+        }
+    }
+    needs_folder.extend(invalid);
+    for name in needs_folder {
+        let sanitized = names.sanitize(name);
+        let mut folder = sanitized.clone();
+        let mut counter = 0;
+        while used.contains(&names.key(&folder)) {
+            counter += 1;
+            folder = names.numbered(&sanitized, counter);
+        }
+        used.insert(names.key(&folder));
+        folder_of.insert(name, folder);
+    }
+
+    albums
+        .into_iter()
+        .map(|album| {
+            let normalized = names.normalize(album);
+            let folder = folder_of[normalized.as_str()].clone();
+            if folder != normalized {
+                renamed.push(RenamedName::album(album, &folder));
+            }
+            (album, folder)
+        })
+        .collect()
+}
+
 /// Write album folders under `<output>/albums/<album_name>/`
+/// Returns the album files created and any that failed. Media in `failed` are skipped.
+/// Album folder names changed for the target filesystem are added to `renamed`.
+#[allow(clippy::too_many_arguments)]
 fn write_album_folders(
     media: &[Media],
+    zip_paths: &[String],
     assignments: &[PathBuf],
+    failed: &HashSet<usize>,
     output_dir: &Path,
-    use_symlinks: bool,
-) -> anyhow::Result<()> {
+    options: &WriteOptions,
+    renamed: &mut Vec<RenamedName>,
+) -> (Vec<PathBuf>, Vec<FailedItem>) {
+    let WriteOptions {
+        album_link: use_symlinks,
+        force,
+        durability,
+        names,
+        ..
+    } = *options;
     let albums_dir = output_dir.join("albums");
-    let mut count = 0u32;
-    // Track used paths per album to avoid collisions
-    let mut used_by_album: HashMap<String, HashSet<PathBuf>> = HashMap::new();
+    let mut album_files = Vec::new();
+    let mut album_failed = Vec::new();
+    // Path keys taken in this run, to avoid collisions
+    let mut used: HashSet<PathBuf> = HashSet::new();
+    let mut comparer = ContentComparer::new(zip_paths, options.hash_algorithm);
+    let album_dirs = assign_album_folders(media, names, renamed);
 
-    for (m, dest) in media.iter().zip(assignments.iter()) {
+    for (idx, (m, dest)) in media.iter().zip(assignments.iter()).enumerate() {
+        if failed.contains(&idx) {
+            continue;
+        }
         for album_name in &m.albums {
-            let album_dir = albums_dir.join(album_name);
-            fs::create_dir_all(&album_dir)?;
-
-            // Get or create the used paths set for this album
-            let used = used_by_album.entry(album_name.clone()).or_default();
-
-            // Resolve filename collision
-            let mut album_file = album_dir.join(&m.filename);
-            if used.contains(&album_file) || album_file.exists() {
-                let stem = Path::new(&m.filename)
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("file");
-                let ext = Path::new(&m.filename)
-                    .extension()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("");
-                let mut counter = 1u32;
-                loop {
-                    let new_name = if ext.is_empty() {
-                        format!("{}({})", stem, counter)
-                    } else {
-                        format!("{}({}).{}", stem, counter, ext)
-                    };
-                    album_file = album_dir.join(&new_name);
-                    if !used.contains(&album_file) && !album_file.exists() {
-                        break;
-                    }
-                    counter += 1;
-                }
+            let album_dir = albums_dir.join(&album_dirs[album_name.as_str()]);
+            if let Err(e) = fs::create_dir_all(&album_dir) {
+                album_failed.push(FailedItem::new(zip_paths, m, &album_dir, FailureCause::from_io(&e)));
+                continue;
+            }
+
+            // Existing copies and links (followed to their target) count like
+            // existing year-folder files; force overwrites them all
+            let (album_file, present) =
+                plan_album_entry(m, &album_dir, options, &mut used, &mut comparer, |path, _| {
+                    let meta = fs::metadata(path).ok().filter(|meta| meta.is_file() && !force)?;
+                    Some(ExistingFile::local(path.to_path_buf(), meta.len()))
+                });
+            if present {
+                album_files.push(album_file);
+                continue;
             }
-            used.insert(album_file.clone());
 
-            if use_symlinks {
+            let result = if use_symlinks {
                 let rel = pathdiff::diff_paths(dest, &album_dir)
                     .unwrap_or_else(|| dest.to_path_buf());
+                // A link cannot be created over an existing file or link
+                if fs::symlink_metadata(&album_file).is_ok_and(|meta| !meta.is_dir()) {
+                    let _ = fs::remove_file(&album_file);
+                }
                 #[cfg(unix)]
-                std::os::unix::fs::symlink(&rel, &album_file)?;
+                let linked = std::os::unix::fs::symlink(&rel, &album_file);
                 #[cfg(windows)]
-                std::os::windows::fs::symlink_file(&rel, &album_file)?;
+                let linked = std::os::windows::fs::symlink_file(&rel, &album_file);
+                linked
             } else {
-                fs::copy(dest, &album_file)?;
+                copy_atomic(dest, &album_file, durability)
+            };
+            match result {
+                Ok(()) => album_files.push(album_file),
+                Err(e) => album_failed.push(FailedItem::new(zip_paths, m, &album_file, FailureCause::from_io(&e))),
             }
-            count += 1;
         }
     }
 
-    if count > 0 {
-        eprintln!("Wrote {} album file(s) to {}", count, albums_dir.display());
+    if !album_files.is_empty() {
+        eprintln!("Wrote {} album file(s) to {}", album_files.len(), albums_dir.display());
+    }
+    (album_files, album_failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sanitize::{CaseSensitivity, NameProfile};
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+
+    fn write_test_zip(zip_path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(zip_path).unwrap());
+        for (name, data) in files {
+            zip.start_file(format!("Takeout/Google Photos/Photos from 2023/{}", name), SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_extract_verified_detects_corruption() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("photo.jpg", stored).unwrap();
+        zip.write_all(b"original photo bytes").unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.path().join("photo.jpg");
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert_eq!(extract_verified(&mut archive.by_index(0).unwrap(), &dest, Durability::None).unwrap(), None);
+        assert_eq!(fs::read(&dest).unwrap(), b"original photo bytes");
+
+        // Flip one byte of the stored data
+        let mut bytes = fs::read(&zip_path).unwrap();
+        let pos = bytes.windows(8).position(|w| w == b"original").unwrap();
+        bytes[pos] ^= 0xff;
+        fs::write(&zip_path, &bytes).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let result = write_entry_atomic(&mut archive.by_index(0).unwrap(), &dest, None, Durability::File).unwrap();
+        assert!(matches!(result, Some(FailureCause::ChecksumMismatch { .. })));
+        // The good copy from before is untouched and no temp file is left behind
+        assert_eq!(fs::read(&dest).unwrap(), b"original photo bytes");
+        assert!(!temp_path_for(&dest).exists());
+    }
+
+    #[test]
+    fn test_interrupted_write_leaves_no_partial_file() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("test.zip");
+        // Compressible data, so damaged bytes break decoding partway through
+        let data: Vec<u8> = (0..200_000u32).flat_map(|i| format!("{} ", i % 977).into_bytes()).collect();
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("photo.jpg", SimpleFileOptions::default()).unwrap();
+        zip.write_all(&data).unwrap();
+        zip.finish().unwrap();
+
+        let (start, len) = {
+            let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+            let entry = archive.by_index(0).unwrap();
+            (entry.data_start() as usize, entry.compressed_size() as usize)
+        };
+        let mut bytes = fs::read(&zip_path).unwrap();
+        bytes[start + len / 2..start + len / 2 + 64].fill(0xff);
+        fs::write(&zip_path, &bytes).unwrap();
+
+        let dest = dir.path().join("photo.jpg");
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let result = write_entry_atomic(&mut archive.by_index(0).unwrap(), &dest, None, Durability::None);
+        // Decoding stops partway through; what was extracted so far is discarded
+        assert!(matches!(result, Ok(Some(FailureCause::CorruptEntry { .. }))), "{:?}", result);
+        assert!(!dest.exists());
+        assert!(!temp_path_for(&dest).exists());
+
+        // A failed copy leaves the file it would have replaced alone
+        fs::write(&dest, b"earlier copy").unwrap();
+        assert!(copy_atomic(&dir.path().join("missing.jpg"), &dest, Durability::None).is_err());
+        assert_eq!(fs::read(&dest).unwrap(), b"earlier copy");
+        assert!(!temp_path_for(&dest).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resume_removes_stale_temp_files() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"aaaa"), ("b.jpg", b"bbbb")]);
+
+        // b.jpg fails, so the checkpoint survives with a.jpg written
+        let output = dir.path().join("out");
+        fs::create_dir_all(output.join("b.jpg").join("blocker")).unwrap();
+        let options = crate::ProcessOptions::for_test(&zip_path, &output);
+        crate::process(&options, &|_| {}).unwrap();
+        fs::remove_dir_all(output.join("b.jpg")).unwrap();
+
+        // A crash left half-written temp files behind
+        fs::write(output.join(".b.jpg.gpth-tmp"), b"bb").unwrap();
+        fs::create_dir_all(output.join("2023")).unwrap();
+        fs::write(output.join("2023").join(".c.jpg.gpth-tmp"), b"c").unwrap();
+
+        let control = crate::ProcessControl::new().with_resume(true);
+        let result = crate::process_with_control(&options, &control, &|_| {}).unwrap();
+        assert!(result.failed.is_empty());
+        assert_eq!(result.files_written, 1);
+        assert_eq!(fs::read(output.join("b.jpg")).unwrap(), b"bbbb");
+        assert!(!output.join(".b.jpg.gpth-tmp").exists());
+        assert!(!output.join("2023").join(".c.jpg.gpth-tmp").exists());
+
+        let report = crate::report::RunReport::load(&output).unwrap().unwrap();
+        assert!(report.files.iter().all(|f| !is_temp_file(&f.output_path)));
+        assert_eq!(report.files.len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_file_does_not_abort_run() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"aaaa"), ("b.jpg", b"bbbb")]);
+
+        // A directory in the way makes the final rename of b.jpg fail
+        let output = dir.path().join("out");
+        fs::create_dir_all(output.join("b.jpg").join("blocker")).unwrap();
+        let options = crate::ProcessOptions::for_test(&zip_path, &output);
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!(result.files_written, 1);
+        assert_eq!(result.failed.len(), 1);
+        assert!(matches!(result.failed[0].cause, FailureCause::Io { .. }));
+        assert_eq!(fs::read(output.join("a.jpg")).unwrap(), b"aaaa");
+
+        // The checkpoint is kept so a re-run retries only the failed file
+        let checkpoint = crate::Checkpoint::load(&output).unwrap().unwrap();
+        assert_eq!(checkpoint.failed_items.len(), 1);
+        assert_eq!(checkpoint.written_files.len(), 1);
+    }
+
+    #[test]
+    fn test_cancelled_write_without_checkpoint() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"aaaa")]);
+        let zip_paths = vec![zip_path.to_string_lossy().to_string()];
+        let media = [Media::new("Takeout/Google Photos/Photos from 2023/a.jpg".into(), 0, 0, "a.jpg".into(), 4)];
+        let options = WriteOptions {
+            divide_to_dates: false,
+            album_dest: None,
+            album_link: false,
+            force: false,
+            durability: Durability::None,
+            min_free_bytes: 0,
+            names: NameRules::new(NameProfile::Posix, CaseSensitivity::Sensitive, dir.path()),
+            on_conflict: ConflictPolicy::Rename,
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        let token = crate::CancellationToken::new();
+        token.cancel();
+        let output = dir.path().join("out");
+        let progress = ThrottledProgress::new(&|_| {});
+        let result = write_output(&media, &zip_paths, &output, &options, &HashMap::new(), &progress, None, Some(&token));
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(!output.join("a.jpg").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resume_rewrites_drifted_files() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"aaaa"), ("b.jpg", b"bbbb"), ("c.jpg", b"cccc")]);
+
+        // b.jpg fails, so the checkpoint survives with a.jpg and c.jpg written
+        let output = dir.path().join("out");
+        fs::create_dir_all(output.join("b.jpg").join("blocker")).unwrap();
+        let options = crate::ProcessOptions::for_test(&zip_path, &output);
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!(result.failed.len(), 1);
+        fs::remove_dir_all(output.join("b.jpg")).unwrap();
+
+        // Meanwhile a.jpg is deleted and c.jpg edited without changing its size
+        fs::remove_file(output.join("a.jpg")).unwrap();
+        fs::write(output.join("c.jpg"), b"CCCC").unwrap();
+
+        let control = crate::ProcessControl::new().with_resume(true).with_verify_resume(true);
+        let result = crate::process_with_control(&options, &control, &|_| {}).unwrap();
+        let mut drifted: Vec<_> = result
+            .drifted
+            .iter()
+            .map(|d| (d.output_path.file_name().unwrap().to_string_lossy().to_string(), d.reason.clone()))
+            .collect();
+        drifted.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            drifted,
+            [
+                ("a.jpg".to_string(), crate::checkpoint::DriftReason::Missing),
+                ("c.jpg".to_string(), crate::checkpoint::DriftReason::ContentChanged),
+            ]
+        );
+        assert!(result.failed.is_empty());
+        assert_eq!(result.files_written, 3);
+        for (name, data) in [("a.jpg", b"aaaa"), ("b.jpg", b"bbbb"), ("c.jpg", b"cccc")] {
+            assert_eq!(fs::read(output.join(name)).unwrap(), data);
+        }
+        assert!(!output.join("c(1).jpg").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resume_migrates_written_files() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"aaaa"), ("b.jpg", b"bbbb"), ("c.jpg", b"cccc")]);
+
+        // b.jpg fails, so the checkpoint survives with a.jpg and c.jpg written
+        let output = dir.path().join("out");
+        fs::create_dir_all(output.join("b.jpg").join("blocker")).unwrap();
+        let mut options = crate::ProcessOptions::for_test(&zip_path, &output);
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!(result.failed.len(), 1);
+        fs::remove_dir_all(output.join("b.jpg")).unwrap();
+
+        // Dividing by date changes every output path
+        options.divide_to_dates = true;
+        let control = crate::ProcessControl::new().with_resume(true).with_migrate(true);
+        let result = crate::process_with_control(&options, &control, &|_| {}).unwrap();
+        assert!(result.failed.is_empty());
+        assert_eq!(result.files_moved, 2);
+        assert_eq!(result.files_written, 1);
+        for (name, data) in [("a.jpg", b"aaaa"), ("b.jpg", b"bbbb"), ("c.jpg", b"cccc")] {
+            assert_eq!(fs::read(output.join("date-unknown").join(name)).unwrap(), data);
+            assert!(!output.join(name).exists());
+        }
+
+        let verify = crate::verify::verify_output(&output, &crate::ThrottledProgress::new(&|_| {})).unwrap();
+        assert!(verify.is_ok(), "{:?}", verify);
+    }
+
+    #[test]
+    fn test_case_insensitive_collisions() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("IMG_1.JPG", b"aaaa"), ("img_1.jpg", b"bbbbbb")]);
+
+        let output = dir.path().join("out");
+        let mut options = crate::ProcessOptions::for_test(&zip_path, &output);
+        options.case_sensitivity = crate::sanitize::CaseSensitivity::Insensitive;
+        crate::process(&options, &|_| {}).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&output)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|n| !n.starts_with(".gpth-"))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["IMG_1.JPG", "img_1(1).jpg"]);
+    }
+
+    #[test]
+    fn test_conflict_policies() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"real")]);
+
+        let run = |policy: ConflictPolicy, existing: &[u8]| {
+            let output = dir.path().join(policy.as_str());
+            fs::create_dir_all(&output).unwrap();
+            // Same name and size as the zip entry, different content
+            fs::write(output.join("a.jpg"), existing).unwrap();
+            let mut options = crate::ProcessOptions::for_test(&zip_path, &output);
+            options.on_conflict = policy;
+            let result = crate::process(&options, &|_| {}).unwrap();
+            let copy = fs::read(output.join("a(1).jpg")).ok();
+            (result.files_skipped, fs::read(output.join("a.jpg")).unwrap(), copy)
+        };
+
+        assert_eq!(run(ConflictPolicy::Rename, b"fake"), (1, b"fake".to_vec(), None));
+        assert_eq!(run(ConflictPolicy::Skip, b"other size"), (1, b"other size".to_vec(), None));
+        assert_eq!(run(ConflictPolicy::Overwrite, b"fake"), (0, b"real".to_vec(), None));
+        assert_eq!(run(ConflictPolicy::Compare, b"fake"), (0, b"fake".to_vec(), Some(b"real".to_vec())));
+
+        // Compare keeps an identical file, and a second run keeps the renamed
+        // copy even without the library recording that it was imported
+        fs::remove_file(dir.path().join("compare").join(crate::library::LIBRARY_FILENAME)).unwrap();
+        let mut options = crate::ProcessOptions::for_test(&zip_path, &dir.path().join("compare"));
+        options.on_conflict = ConflictPolicy::Compare;
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!((result.files_written, result.files_skipped), (0, 1));
+    }
+
+    #[test]
+    fn test_rerun_keeps_album_entries() {
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for name in ["Photos from 2023/IMG_1.jpg", "Trip/IMG_1.jpg"] {
+            zip.start_file(format!("Takeout/Google Photos/{}", name), SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"photo").unwrap();
+        }
+        zip.finish().unwrap();
+
+        for album_link in [false, true] {
+            let output = dir.path().join(format!("out-{}", album_link));
+            let mut options = crate::ProcessOptions {
+                albums: true,
+                album_dest: "album".to_string(),
+                album_link,
+                ..crate::ProcessOptions::for_test(&zip_path, &output)
+            };
+            for policy in [ConflictPolicy::Rename, ConflictPolicy::Compare, ConflictPolicy::Skip] {
+                options.on_conflict = policy;
+                crate::process(&options, &|_| {}).unwrap();
+                let album: Vec<_> = fs::read_dir(output.join("albums/Trip")).unwrap().map(|e| e.unwrap().file_name()).collect();
+                assert_eq!(album, ["IMG_1.jpg"], "{} after --on-conflict {}", output.display(), policy);
+            }
+
+            let verify = crate::verify::verify_output(&output, &crate::ThrottledProgress::new(&|_| {})).unwrap();
+            assert!(verify.is_ok(), "{:?}", verify);
+        }
+    }
+
+    #[test]
+    fn test_archive_output() {
+        use crate::sink::OutputFormat;
+        use std::io::Read;
+
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("takeout.zip");
+        write_test_zip(&zip_path, &[("a.jpg", b"aaaa"), ("b.jpg", b"bb")]);
+
+        let archive_path = dir.path().join("out/photos.zip");
+        let mut options = crate::ProcessOptions::for_test(&zip_path, &archive_path);
+        options.output_format = OutputFormat::Zip;
+        let result = crate::process(&options, &|_| {}).unwrap();
+        assert_eq!(result.files_written, 2);
+        assert!(!dir.path().join("out/photos.zip.gpth-tmp").exists());
+
+        let mut archive = ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, [crate::report::REPORT_FILENAME, "a.jpg", "b.jpg"]);
+        let mut entry = archive.by_name("a.jpg").unwrap();
+        assert_eq!(entry.compression(), zip::CompressionMethod::Stored);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"aaaa");
+
+        let tar_path = dir.path().join("photos.tar");
+        let mut options = crate::ProcessOptions::for_test(&zip_path, &tar_path);
+        options.output_format = OutputFormat::Tar;
+        crate::process(&options, &|_| {}).unwrap();
+        let tar = fs::read(&tar_path).unwrap();
+        assert_eq!(tar.len() % 512, 0);
+        assert_eq!(&tar[..5], b"a.jpg");
+        assert_eq!(&tar[512..516], b"aaaa");
     }
-    Ok(())
 }
@@ -5,12 +5,13 @@ use std::path::Path;
 
 use chrono::NaiveDateTime;
 use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
 
 use crate::date;
 use crate::extras;
 use crate::folder_classify;
 use crate::media::Media;
-use crate::ThrottledProgress;
+use crate::{Error, Result, Stage, ThrottledProgress};
 
 /// Decode ZIP entry name, trying UTF-8 first, then Shift_JIS
 fn decode_zip_name(entry: &zip::read::ZipFile) -> String {
@@ -32,7 +33,7 @@ fn decode_zip_name(entry: &zip::read::ZipFile) -> String {
 }
 
 /// An entry found in an album folder
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlbumEntry {
     pub filename: String,
     pub zip_path: String,
@@ -42,6 +43,7 @@ pub struct AlbumEntry {
 }
 
 /// Result of scanning all zip files
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanResult {
     /// Media files found (in year folders only)
     pub media: Vec<Media>,
@@ -52,15 +54,23 @@ pub struct ScanResult {
 }
 
 /// Scan all zip files, collecting media entries and JSON dates
-pub fn scan_zips(zip_paths: &[String], skip_extras: bool, scan_albums: bool, progress: &ThrottledProgress) -> anyhow::Result<ScanResult> {
+pub fn scan_zips(zip_paths: &[String], skip_extras: bool, scan_albums: bool, progress: &ThrottledProgress) -> Result<ScanResult> {
     let mut media = Vec::new();
     let mut json_dates: HashMap<String, NaiveDateTime> = HashMap::new();
     let mut album_entries: HashMap<String, Vec<AlbumEntry>> = HashMap::new();
 
-    for (zip_index, zip_path) in zip_paths.iter().enumerate() {
-        let file = File::open(zip_path)?;
-        let mut archive = zip::ZipArchive::new(file)?;
-        let total = archive.len() as u64;
+    // Open every ZIP first so progress counts entries across all of them
+    let mut archives = Vec::with_capacity(zip_paths.len());
+    for zip_path in zip_paths {
+        let path = Path::new(zip_path);
+        let file = File::open(path).map_err(Error::zip(path))?;
+        archives.push(zip::ZipArchive::new(file).map_err(Error::zip(path))?);
+    }
+    let total: u64 = archives.iter().map(|a| a.len() as u64).sum();
+    let mut done = 0u64;
+    progress.start(Stage::Scan, total);
+
+    for (zip_index, (zip_path, mut archive)) in zip_paths.iter().zip(archives).enumerate() {
 
         let zip_name = Path::new(zip_path)
             .file_name()
@@ -69,8 +79,8 @@ pub fn scan_zips(zip_paths: &[String], skip_extras: bool, scan_albums: bool, pro
             .to_string();
 
         for i in 0..archive.len() {
-            progress.report("scan", i as u64, total, &format!("Scanning {}", zip_name));
-            let entry = archive.by_index(i)?;
+            progress.report(Stage::Scan, done + i as u64, total, &format!("Scanning {}", zip_name));
+            let entry = archive.by_index(i).map_err(Error::zip(Path::new(zip_path)))?;
             let entry_path = decode_zip_name(&entry);
 
             if entry.is_dir() {
@@ -90,9 +100,9 @@ pub fn scan_zips(zip_paths: &[String], skip_extras: bool, scan_albums: bool, pro
             // Parse JSON metadata and register date with all variants
             if entry_path.ends_with(".json") {
                 drop(entry);
-                let mut json_entry = archive.by_index(i)?;
+                let mut json_entry = archive.by_index(i).map_err(Error::zip(Path::new(zip_path)))?;
                 let mut bytes = Vec::new();
-                json_entry.read_to_end(&mut bytes)?;
+                json_entry.read_to_end(&mut bytes).map_err(Error::zip(Path::new(zip_path)))?;
                 if let Some(dt) = date::json::parse_google_json(&bytes) {
                     date::json::register_json_date(&entry_path, dt, &mut json_dates);
                 }
@@ -151,8 +161,10 @@ pub fn scan_zips(zip_paths: &[String], skip_extras: bool, scan_albums: bool, pro
 
             media.push(Media::new(entry_path, zip_index, i, filename, size));
         }
-        progress.report("scan", total, total, &format!("Scanned {}", zip_name));
+        done += archive.len() as u64;
+        progress.report(Stage::Scan, done, total, &format!("Scanned {}", zip_name));
     }
+    progress.finish(Stage::Scan);
 
     Ok(ScanResult {
         media,
@@ -133,24 +133,97 @@ selectOutputBtn.onclick = async () => {
 const stageLabels: Record<string, string> = {
   scan: "Scanning ZIP files",
   date: "Extracting dates",
-  "date-exif": "Reading EXIF data",
-  "date-exif-album": "Reading EXIF (albums)",
+  exif: "Reading EXIF data",
+  album_exif: "Reading EXIF (albums)",
   dedup: "Deduplicating",
+  library: "Matching earlier imports",
   write: "Writing files",
+  verify: "Verifying files",
 };
 
-listen<{ stage: string; current: number; total: number; message: string }>(
-  "progress",
-  (event) => {
-    const p = event.payload;
-    progressSection.hidden = false;
-    progressStage.textContent = stageLabels[p.stage] || p.stage;
-    const pct = p.total > 0 ? Math.round((p.current / p.total) * 100) : 0;
-    progressPct.textContent = pct + "%";
-    progressFill.style.width = pct + "%";
-    progressDetail.textContent = `${p.current.toLocaleString()} / ${p.total.toLocaleString()}`;
+// Mirrors gpth_core::ProgressEvent (FileSkipped is not forwarded)
+type ProgressEvent =
+  | { kind: "stage_started"; stage: string; total: number }
+  | { kind: "progress"; stage: string; current: number; total: number; message: string }
+  | { kind: "bytes"; stage: string; done: number; total: number; bytes_per_sec: number; eta_secs: number | null }
+  | { kind: "stage_finished"; stage: string; elapsed_ms: number; bytes: number; bytes_per_sec: number }
+  | { kind: "paused"; stage: string; message: string }
+  | { kind: "warning"; message: string }
+  | { kind: "checkpoint_saved"; written_files: number }
+  | { kind: "resumed"; written_files: number; migrating: boolean }
+  | { kind: "stage_restored"; stage: string; media: number };
+
+// Error from run_process; kind is a gpth_core::Error variant such as "invalid_zip" or "cancelled"
+type ProcessError = { kind: string; message: string; hint: string | null };
+
+function formatBytes(bytes: number): string {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit++;
   }
-);
+  return unit === 0 ? `${value} B` : `${value.toFixed(1)} ${units[unit]}`;
+}
+
+function formatDuration(secs: number): string {
+  if (secs < 60) return `${secs}s`;
+  if (secs < 3600) return `${Math.floor(secs / 60)}m${String(secs % 60).padStart(2, "0")}s`;
+  return `${Math.floor(secs / 3600)}h${String(Math.floor((secs % 3600) / 60)).padStart(2, "0")}m`;
+}
+
+function showProgress(stage: string, current: number, total: number, detail: string) {
+  progressSection.hidden = false;
+  progressStage.textContent = stageLabels[stage] || stage;
+  const pct = total > 0 ? Math.round((current / total) * 100) : 0;
+  progressPct.textContent = pct + "%";
+  progressFill.style.width = pct + "%";
+  progressDetail.textContent = detail;
+}
+
+listen<ProgressEvent>("progress", (event) => {
+  const p = event.payload;
+  switch (p.kind) {
+    case "stage_started":
+      showProgress(p.stage, 0, p.total, `0 / ${p.total.toLocaleString()}`);
+      break;
+    case "progress":
+      showProgress(p.stage, p.current, p.total, `${p.current.toLocaleString()} / ${p.total.toLocaleString()}`);
+      break;
+    case "bytes": {
+      // Bytes track the time left better than file counts; the bar follows them
+      const pct = p.total > 0 ? Math.round((p.done / p.total) * 100) : 0;
+      progressPct.textContent = pct + "%";
+      progressFill.style.width = pct + "%";
+      const eta = p.eta_secs === null ? "--" : formatDuration(p.eta_secs);
+      progressDetail.textContent +=
+        ` · ${formatBytes(p.done)} / ${formatBytes(p.total)} · ${formatBytes(Math.round(p.bytes_per_sec))}/s · ETA ${eta}`;
+      break;
+    }
+    case "stage_finished": {
+      const secs = (p.elapsed_ms / 1000).toFixed(1);
+      const rate = p.bytes > 0 ? `, ${formatBytes(p.bytes)} at ${formatBytes(Math.round(p.bytes_per_sec))}/s` : "";
+      log(`${stageLabels[p.stage] || p.stage}: done in ${secs}s${rate}`);
+      break;
+    }
+    case "paused":
+      progressStage.textContent = "Paused: " + p.message;
+      break;
+    case "warning":
+      log("Warning: " + p.message);
+      break;
+    case "checkpoint_saved":
+      break;
+    case "resumed":
+      log(`Resuming from checkpoint: ${p.written_files.toLocaleString()} files already written`);
+      if (p.migrating) log("Output paths changed; written files will be moved to their new locations");
+      break;
+    case "stage_restored":
+      log(`Resuming at ${stageLabels[p.stage] || p.stage}: ${p.media.toLocaleString()} media restored`);
+      break;
+  }
+});
 
 // Pause/Resume
 pauseBtn.onclick = async () => {
@@ -226,15 +299,19 @@ runBtn.onclick = async () => {
     resultText.textContent = result;
     log("Done: " + result);
   } catch (e) {
-    const errMsg = String(e);
-    log("Error: " + errMsg);
-    if (errMsg.includes("cancelled") || errMsg.includes("Cancelled")) {
+    const err = e as ProcessError;
+    log("Error: " + err.message);
+    if (err.kind === "cancelled") {
       progressStage.textContent = "Cancelled";
-      progressDetail.textContent = "Checkpoint saved. Enable 'Resume' to continue.";
+      progressDetail.textContent = "Checkpoint saved. Run again to continue.";
     } else {
       progressStage.textContent = "Error";
+      progressDetail.textContent = err.hint ?? "";
       progressFill.style.width = "100%";
       progressFill.style.background = "var(--red)";
+      if (err.hint) {
+        log(err.hint);
+      }
     }
   } finally {
     runBtn.disabled = false;
@@ -1,7 +1,26 @@
 use std::sync::{Arc, Mutex};
-use gpth_core::{CancellationToken, ProcessControl, ProcessOptions, Progress};
+use gpth_core::{CancellationToken, ProcessControl, ProcessOptions, ProgressEvent};
 use tauri::{Emitter, State};
 
+/// Error returned by `run_process`: `kind` is the `gpth_core::Error` variant
+/// (or "panic"), `hint` a remedy to show next to the message.
+#[derive(serde::Serialize)]
+struct ProcessError {
+    kind: &'static str,
+    message: String,
+    hint: Option<String>,
+}
+
+impl From<gpth_core::Error> for ProcessError {
+    fn from(e: gpth_core::Error) -> Self {
+        Self {
+            kind: e.kind(),
+            message: format!("{:#}", e),
+            hint: e.hint(),
+        }
+    }
+}
+
 /// Shared state for process control
 struct ProcessState {
     cancel_token: Mutex<Option<CancellationToken>>,
@@ -21,7 +40,7 @@ async fn run_process(
     force: bool,
     window: tauri::Window,
     state: State<'_, Arc<ProcessState>>,
-) -> Result<String, String> {
+) -> Result<String, ProcessError> {
     // If force mode, delete any existing checkpoint
     if force {
         let _ = gpth_core::Checkpoint::delete(&options.output);
@@ -37,16 +56,11 @@ async fn run_process(
     let state_clone = state.inner().clone();
 
     let handle = std::thread::spawn(move || {
-        let cb = move |stage: &str, current: u64, total: u64, message: &str| {
-            let _ = window.emit(
-                "progress",
-                Progress {
-                    stage: stage.to_string(),
-                    current,
-                    total,
-                    message: message.to_string(),
-                },
-            );
+        let cb = move |event: &ProgressEvent| {
+            // One per unchanged file on a resumed run; the summary has the count
+            if !matches!(event, ProgressEvent::FileSkipped { .. }) {
+                let _ = window.emit("progress", event);
+            }
         };
 
         // Auto-resume unless force mode
@@ -65,22 +79,41 @@ async fn run_process(
         result
     });
 
-    let result = handle
-        .join()
-        .map_err(|_| "Processing thread panicked".to_string())?;
+    let result = handle.join().map_err(|_| ProcessError {
+        kind: "panic",
+        message: "Processing thread panicked".to_string(),
+        hint: None,
+    })?;
 
     match result {
-        Ok(result) => Ok(format!(
-            "{} media files processed, {} duplicates removed, {} files written, {} skipped",
-            result.total_media, result.duplicates_removed, result.files_written, result.files_skipped
-        )),
-        Err(e) => {
-            if e.downcast_ref::<gpth_core::CancelledError>().is_some() {
-                Err("Processing cancelled. Checkpoint saved.".to_string())
-            } else {
-                Err(e.to_string())
+        Ok(result) => {
+            let mut summary = format!(
+                "{} media files processed, {} duplicates removed, {} files written, {} skipped",
+                result.total_media, result.duplicates_removed, result.files_written, result.files_skipped
+            );
+            if result.files_moved > 0 {
+                summary.push_str(&format!(", {} moved", result.files_moved));
+            }
+            if !result.drifted.is_empty() {
+                summary.push_str(&format!(", {} rewritten after changing since the interruption", result.drifted.len()));
+            }
+            if result.already_imported > 0 {
+                summary.push_str(&format!(", {} already imported", result.already_imported));
+            }
+            if !result.failed.is_empty() {
+                summary.push_str(&format!(", {} failed", result.failed.len()));
+                for item in &result.failed {
+                    summary.push_str(&format!("\n{}: {}", item.zip_path, item.cause));
+                }
             }
+            Ok(summary)
         }
+        Err(gpth_core::Error::Cancelled) => Err(ProcessError {
+            kind: "cancelled",
+            message: "Processing cancelled. Checkpoint saved.".to_string(),
+            hint: gpth_core::Error::Cancelled.hint(),
+        }),
+        Err(e) => Err(e.into()),
     }
 }
 